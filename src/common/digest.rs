@@ -0,0 +1,100 @@
+use std::io::{self, Read, Seek};
+
+#[cfg(feature = "sha1")]
+use sha1::Sha1;
+#[cfg(feature = "sha2")]
+use sha2::Sha256;
+#[cfg(feature = "sha2")]
+use crate::TarError;
+
+use super::meta::Header;
+use super::read::entry_reader;
+
+#[cfg(feature = "sha1")]
+impl Header {
+    /// SHA-1 of entry data. With `git_blob` data is prefixed by `blob <size>\0`
+    /// so result is the same as git object id (`git hash-object`).
+    pub fn sha1<S: Read + Seek>(&self, source: &mut S, git_blob: bool) -> io::Result<[u8; 20]> {
+        use sha1::Digest;
+
+        let mut hasher = Sha1::new();
+        if git_blob {
+            hasher.update(format!("blob {}\0", self.size));
+        }
+        io::copy(&mut entry_reader(source, self)?, &mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+}
+
+/// PAX keys that could contain hex encoded SHA-256 of entry data.
+#[cfg(feature = "sha2")]
+const SHA256_KEYS: [&str; 2] = ["sha256", "SCHILY.sha256"];
+
+/// Verify entry data against digest recorded in PAX extended header.
+/// Returns `TarError::NoDigest` if there is no supported digest record for the entry.
+#[cfg(feature = "sha2")]
+pub fn verify_data<S: Read + Seek>(source: &mut S, header: &Header) -> Result<bool, TarError> {
+    use sha2::Digest;
+
+    let expected = SHA256_KEYS
+        .iter()
+        .find_map(|k| header.pax_record(k))
+        .ok_or(TarError::NoDigest)?;
+    let expected = String::from_utf8_lossy(expected).trim().to_lowercase();
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut entry_reader(source, header)?, &mut hasher)?;
+    let actual = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    Ok(actual == expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest2::prelude::*;
+
+    use super::*;
+    use super::super::read::HeadersParser;
+    use super::super::testutil::*;
+
+    // sha256 of "abc"
+    #[cfg(feature = "sha2")]
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    #[cfg(feature = "sha2")]
+    fn verify_sha256_record() {
+        let mut source = archive(&[
+            pax(&[("sha256", ABC_SHA256)]),
+            file("good.txt", b"abc"),
+            pax(&[("sha256", ABC_SHA256)]),
+            file("bad.txt", b"abd"),
+            file("none.txt", b"abc"),
+        ]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(3));
+
+        assert_that!(verify_data(&mut source, &headers[0]).unwrap(), equal_to(true));
+        assert_that!(verify_data(&mut source, &headers[1]).unwrap(), equal_to(false));
+        assert!(matches!(verify_data(&mut source, &headers[2]), Err(TarError::NoDigest)));
+    }
+
+    #[test]
+    #[cfg(feature = "sha1")]
+    fn sha1_git_blob() {
+        let mut file = std::fs::File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+        let hex = |digest: [u8; 20]| digest.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        // git hash-object test/file_2.txt
+        let blob = headers[1].sha1(&mut file, true).unwrap();
+        assert_that!(hex(blob), equal_to("b8947b77094228836f18792dc5fac15dfa9de11e".to_string()));
+        // sha1sum test/file_2.txt
+        let plain = headers[1].sha1(&mut file, false).unwrap();
+        assert_that!(hex(plain), equal_to("19c32940f2e5f997bc34530cf9b544247e674168".to_string()));
+    }
+}
@@ -0,0 +1,527 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::TarError;
+
+use super::meta::{HeaderType, Mode, TarMeta};
+use super::offset_by_blocks;
+use super::read::{entry_reader, HeadersParser};
+
+/// Disk space required to extract all entries data (sizes rounded up to blocks).
+pub fn required_space(meta: &TarMeta) -> u64 {
+    meta.iter().map(|h| offset_by_blocks(h.size) as u64).sum()
+}
+
+/// Convert entry name into relative path without `strip` leading components.
+/// Returns None for names that could escape destination directory or become empty.
+fn entry_path(name: &str, strip: usize) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    let mut skipped = 0;
+    for c in Path::new(name).components() {
+        match c {
+            Component::Normal(_) if skipped < strip => skipped += 1,
+            Component::Normal(p) => path.push(p),
+            Component::ParentDir => return None,
+            _ => {}
+        }
+    }
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Check that symbolic link target resolves inside destination: it is relative and `..`
+/// components never go above the root (counted from the directory of link itself).
+fn link_target_inside(path: &Path, target: &Path) -> bool {
+    let mut depth = path.components().count().saturating_sub(1);
+    for c in target.components() {
+        match c {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Stream data of entry with given name to writer, returns false if there is no such entry.
+/// Source is scanned from the beginning, the last revision wins for appended archives.
+pub fn extract_file<S, W>(source: &mut S, name: &str, out: &mut W) -> io::Result<bool>
+where
+    S: Read + Seek,
+    W: Write,
+{
+    let found = HeadersParser::from(source).filter(|h| h.name == name).last();
+    match found {
+        Some(h) => {
+            io::copy(&mut entry_reader(source, &h)?, out)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Remove whatever is at path before creating link there.
+fn remove_existing(path: &Path) -> std::io::Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Extracts archive entries to the file system.
+#[derive(Debug, Default)]
+pub struct Extractor {
+    #[cfg(feature = "fs2")]
+    check_free_space: bool,
+    preserve_special_bits: bool,
+    umask: u32,
+    strip_components: usize,
+    rename: Option<Rename>,
+}
+
+type RenameFn = dyn FnMut(&str) -> Option<String>;
+
+/// Mapping of output paths, called from `extract(&self)` so it is kept in RefCell.
+struct Rename(RefCell<Box<RenameFn>>);
+
+impl std::fmt::Debug for Rename {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Rename")
+    }
+}
+
+impl Extractor {
+    pub fn new() -> Extractor {
+        Extractor::default()
+    }
+
+    /// Keep setuid, setgid and sticky bits from archive (stripped by default for safety).
+    pub fn preserve_special_bits(mut self, preserve: bool) -> Self {
+        self.preserve_special_bits = preserve;
+        self
+    }
+
+    /// Permission bits cleared from every extracted file mode, e.g. 0o022.
+    pub fn umask(mut self, umask: u32) -> Self {
+        self.umask = umask;
+        self
+    }
+
+    /// Drop `n` leading path components from entry names (and hard link targets) like
+    /// `tar --strip-components`. Entries with nothing left are skipped.
+    pub fn strip_components(mut self, n: usize) -> Self {
+        self.strip_components = n;
+        self
+    }
+
+    /// Map output path of every entry (and hard link target), entries mapped to None are skipped.
+    /// Function gets path after `strip_components`, result is checked for escaping destination too.
+    pub fn rename<F: FnMut(&str) -> Option<String> + 'static>(mut self, rename: F) -> Self {
+        self.rename = Some(Rename(RefCell::new(Box::new(rename))));
+        self
+    }
+
+    /// Relative output path for entry name, None if entry should be skipped.
+    fn output_path(&self, name: &str) -> Option<PathBuf> {
+        let path = entry_path(name, self.strip_components)?;
+        match &self.rename {
+            Some(Rename(rename)) => {
+                let renamed = (rename.borrow_mut())(&path.to_string_lossy())?;
+                entry_path(&renamed, 0)
+            }
+            None => Some(path),
+        }
+    }
+
+    /// File mode to set for extracted entry.
+    fn file_mode(&self, mode: u64) -> u32 {
+        let mut mode = (mode & 0o7777) as u32;
+        if !self.preserve_special_bits {
+            mode &= !((Mode::TSUID | Mode::TSGID | Mode::TSVTX) as u32);
+        }
+        mode & !self.umask
+    }
+
+    /// Abort extraction if destination has less free space than `required_space`.
+    #[cfg(feature = "fs2")]
+    pub fn check_free_space(mut self, check: bool) -> Self {
+        self.check_free_space = check;
+        self
+    }
+
+    /// Extract all valid entries from source into destination directory.
+    /// Entries with names pointing outside of destination are skipped, so are symbolic links
+    /// with absolute targets or targets going above destination via `..`.
+    pub fn extract<S, P>(&self, source: &mut S, dst: P) -> Result<(), TarError>
+    where
+        S: Read + Seek,
+        P: AsRef<Path>,
+    {
+        let dst = dst.as_ref();
+        fs::create_dir_all(dst)?;
+
+        #[cfg(feature = "fs2")]
+        {
+            if self.check_free_space {
+                let meta = TarMeta::from(HeadersParser::from(source.by_ref()));
+                let required = required_space(&meta);
+                let available = fs2::available_space(dst)?;
+                if available < required {
+                    return Err(TarError::NotEnoughSpace { required, available });
+                }
+            }
+        }
+
+        self.extract_to(source, &mut FsSink::from(dst))
+    }
+
+    /// Extract all valid entries from source into sink, paths passed to sink are relative.
+    pub fn extract_to<S, K>(&self, source: &mut S, sink: &mut K) -> Result<(), TarError>
+    where
+        S: Read + Seek,
+        K: FileSink,
+    {
+        let meta = TarMeta::from(HeadersParser::from(source.by_ref()));
+        for h in meta.iter() {
+            let path = match self.output_path(&h.name) {
+                Some(p) => p,
+                None => continue,
+            };
+            match h.effective_type() {
+                HeaderType::Dir | HeaderType::Dumpdir => sink.create_dir(&path)?,
+                HeaderType::Reg | HeaderType::Cont => {
+                    let mut data = entry_reader(source, h)?;
+                    sink.write_file(&path, &mut data, h.size as u64, self.file_mode(h.mode))?;
+                }
+                HeaderType::Link => {
+                    if let Some(target) = self.output_path(&h.linkname) {
+                        sink.hard_link(&target, &path)?;
+                    }
+                }
+                HeaderType::Sym => {
+                    let target = Path::new(&h.linkname);
+                    if link_target_inside(&path, target) {
+                        sink.symlink(target, &path)?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Destination of extracted entries, paths are relative to extraction root.
+pub trait FileSink {
+    /// Create directory (with missing parents).
+    fn create_dir(&mut self, path: &Path) -> io::Result<()>;
+    /// Create or replace file with `size` bytes of data, fewer bytes give `UnexpectedEof` error.
+    fn write_file(&mut self, path: &Path, data: &mut dyn Read, size: u64, mode: u32) -> io::Result<()>;
+    /// Create symbolic link at `path` pointing to `target` (stored as is).
+    fn symlink(&mut self, target: &Path, path: &Path) -> io::Result<()>;
+    /// Create hard link at `path` to previously extracted `target`.
+    fn hard_link(&mut self, target: &Path, path: &Path) -> io::Result<()>;
+}
+
+/// File system sink used by `Extractor::extract`.
+#[derive(Debug)]
+pub struct FsSink {
+    root: PathBuf,
+}
+
+impl FsSink {
+    pub fn from<P: AsRef<Path>>(root: P) -> FsSink {
+        FsSink {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Refuse paths going through symbolic links already present under root,
+    /// otherwise entries could be written wherever links point to.
+    fn check_no_symlinks(&self, path: &Path) -> io::Result<()> {
+        let mut current = self.root.clone();
+        for c in path.components() {
+            current.push(c);
+            match fs::symlink_metadata(&current) {
+                Ok(m) if m.file_type().is_symlink() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        format!("path goes through symbolic link {}", current.display()),
+                    ));
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Absolute path with parent directories created.
+    fn prepare(&self, path: &Path) -> io::Result<PathBuf> {
+        if let Some(parent) = path.parent() {
+            self.check_no_symlinks(parent)?;
+        }
+        let path = self.root.join(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        Ok(path)
+    }
+}
+
+impl FileSink for FsSink {
+    fn create_dir(&mut self, path: &Path) -> io::Result<()> {
+        self.check_no_symlinks(path)?;
+        fs::create_dir_all(self.root.join(path))
+    }
+
+    /// Existing file or link at path is replaced, data is never written through a link.
+    fn write_file(&mut self, path: &Path, data: &mut dyn Read, size: u64, mode: u32) -> io::Result<()> {
+        let path = self.prepare(path)?;
+        remove_existing(&path)?;
+        if io::copy(&mut data.take(size), &mut fs::File::create(&path)?)? != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn symlink(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        let path = self.prepare(path)?;
+        remove_existing(&path)?;
+        std::os::unix::fs::symlink(target, &path)
+    }
+
+    /// Symbolic links are not extracted on this platform.
+    #[cfg(not(unix))]
+    fn symlink(&mut self, _target: &Path, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn hard_link(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        self.check_no_symlinks(target)?;
+        let path = self.prepare(path)?;
+        remove_existing(&path)?;
+        fs::hard_link(self.root.join(target), &path)
+    }
+}
+
+/// In-memory sink that keeps files data only, directories and symbolic links are ignored.
+impl FileSink for HashMap<PathBuf, Vec<u8>> {
+    fn create_dir(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &Path, data: &mut dyn Read, size: u64, _mode: u32) -> io::Result<()> {
+        let mut buffer = Vec::new();
+        if data.take(size).read_to_end(&mut buffer)? as u64 != size {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.insert(path.to_path_buf(), buffer);
+        Ok(())
+    }
+
+    fn symlink(&mut self, _target: &Path, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn hard_link(&mut self, target: &Path, path: &Path) -> io::Result<()> {
+        let data = self.get(target).cloned().ok_or(io::ErrorKind::NotFound)?;
+        self.insert(path.to_path_buf(), data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::PathBuf;
+
+    use hamcrest2::prelude::*;
+
+    use super::*;
+    use super::super::testutil::test_resources_path;
+
+    fn output_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ustar_extract_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn required_space_for_archive() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let meta = TarMeta::from(HeadersParser::from(&mut file));
+
+        // 512 + 15 + 521 + 16 bytes rounded up to blocks
+        assert_that!(required_space(&meta), equal_to(512 + 512 + 1024 + 512));
+    }
+
+    #[test]
+    fn extract_single_file() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let mut out = Vec::new();
+
+        assert!(extract_file(&mut file, "file_3_dir/file_3.txt", &mut out).unwrap());
+        let expected = fs::read(test_resources_path().join("file_3_dir/file_3.txt")).unwrap();
+        assert_that!(out, equal_to(expected));
+        assert!(!extract_file(&mut file, "missing.txt", &mut Vec::new()).unwrap());
+    }
+
+    #[test]
+    fn extract_files() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let dst = output_dir("files");
+
+        Extractor::new().extract(&mut file, &dst).unwrap();
+
+        let expected = fs::read(test_resources_path().join("file_3_dir/file_3.txt")).unwrap();
+        let extracted = fs::read(dst.join("file_3_dir/file_3.txt")).unwrap();
+        assert_that!(extracted, equal_to(expected));
+        assert!(dst.join("file_4.txt").is_file());
+        fs::remove_dir_all(&dst).unwrap();
+    }
+
+    #[test]
+    fn extract_to_memory() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let mut sink: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+
+        Extractor::new().extract_to(&mut file, &mut sink).unwrap();
+
+        let mut names = sink.keys().map(|p| p.to_string_lossy().into_owned()).collect::<Vec<String>>();
+        names.sort();
+        assert_that!(names, equal_to(vec!["file_1.txt", "file_2.txt", "file_3_dir/file_3.txt", "file_4.txt"]));
+        let expected = fs::read(test_resources_path().join("file_2.txt")).unwrap();
+        assert_that!(&sink[&PathBuf::from("file_2.txt")], equal_to(&expected));
+    }
+
+    #[test]
+    fn extract_renamed() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let mut sink: HashMap<PathBuf, Vec<u8>> = HashMap::new();
+
+        Extractor::new()
+            .rename(|name| match name {
+                "file_4.txt" => None,
+                _ => Some(format!("out/{}", name.to_uppercase())),
+            })
+            .extract_to(&mut file, &mut sink)
+            .unwrap();
+
+        let mut names = sink.keys().map(|p| p.to_string_lossy().into_owned()).collect::<Vec<String>>();
+        names.sort();
+        assert_that!(names, equal_to(vec!["out/FILE_1.TXT", "out/FILE_2.TXT", "out/FILE_3_DIR/FILE_3.TXT"]));
+    }
+
+    #[test]
+    fn extract_strip_components() {
+        let mut file = File::open(test_resources_path().join("gnu_incremental_test.tar")).unwrap();
+        let dst = output_dir("strip");
+
+        Extractor::new().strip_components(1).extract(&mut file, &dst).unwrap();
+
+        assert_that!(fs::read(dst.join("one.txt")).unwrap(), equal_to(b"one\n".to_vec()));
+        assert!(dst.join("sub/two.txt").is_file());
+        assert!(!dst.join("inc_dir").exists());
+        fs::remove_dir_all(&dst).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_strips_setuid() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use super::super::testutil::{archive, entry, header};
+        use super::super::meta::HeaderProperty;
+
+        let mut ph = header("suid.sh", b'0', 2);
+        ph.set_field(HeaderProperty::Mode, b"0004775");
+        ph.repair_checksum();
+        let data = entry(ph, b"#!");
+        let mode = |dst: &PathBuf| fs::metadata(dst.join("suid.sh")).unwrap().permissions().mode() & 0o7777;
+
+        let dst = output_dir("suid");
+        Extractor::new().umask(0o002).extract(&mut archive(std::slice::from_ref(&data)), &dst).unwrap();
+        assert_that!(mode(&dst), equal_to(0o0775 & !0o002));
+        fs::remove_dir_all(&dst).unwrap();
+
+        let dst = output_dir("suid_preserved");
+        Extractor::new().preserve_special_bits(true).extract(&mut archive(&[data]), &dst).unwrap();
+        assert_that!(mode(&dst), equal_to(0o4775));
+        fs::remove_dir_all(&dst).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_symlinks_stay_inside() {
+        use super::super::testutil::{archive, entry, file, link};
+
+        let outside = output_dir("symlink_outside");
+        fs::create_dir_all(&outside).unwrap();
+        let outside_file = outside.join("victim.txt");
+        fs::write(&outside_file, b"safe").unwrap();
+        let outside_dir = outside.to_string_lossy().into_owned();
+
+        let mut source = archive(&[
+            entry(link("a", b'2', &outside_file.to_string_lossy()), b""),
+            file("a", b"evil"),
+            entry(link("d", b'2', &outside_dir), b""),
+            file("d/x", b"evil"),
+            entry(link("up", b'2', "../.."), b""),
+            entry(link("sub/ok", b'2', "../a"), b""),
+        ]);
+        let dst = output_dir("symlinks");
+        Extractor::new().extract(&mut source, &dst).unwrap();
+
+        assert_that!(fs::read(&outside_file).unwrap(), equal_to(b"safe".to_vec()));
+        assert!(!outside.join("x").exists());
+        assert_that!(fs::read(dst.join("a")).unwrap(), equal_to(b"evil".to_vec()));
+        assert!(fs::symlink_metadata(dst.join("up")).is_err());
+        assert!(fs::symlink_metadata(dst.join("sub/ok")).unwrap().file_type().is_symlink());
+        fs::remove_dir_all(&dst).unwrap();
+
+        // Links already present in destination are not followed
+        fs::create_dir_all(&dst).unwrap();
+        std::os::unix::fs::symlink(&outside_file, dst.join("a")).unwrap();
+        std::os::unix::fs::symlink(&outside, dst.join("d")).unwrap();
+        let mut sink = FsSink::from(&dst);
+        sink.write_file(Path::new("a"), &mut &b"evil"[..], 4, 0o644).unwrap();
+        assert!(sink.write_file(Path::new("d/x"), &mut &b"evil"[..], 4, 0o644).is_err());
+        assert!(sink.write_file(Path::new("short"), &mut &b"evil"[..], 5, 0o644).is_err());
+        assert!(sink.create_dir(Path::new("d/sub")).is_err());
+        assert_that!(fs::read(&outside_file).unwrap(), equal_to(b"safe".to_vec()));
+        assert!(!outside.join("x").exists());
+        assert!(!outside.join("sub").exists());
+
+        fs::remove_dir_all(&dst).unwrap();
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[cfg(feature = "fs2")]
+    #[test]
+    fn extract_with_free_space_check() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let dst = output_dir("free_space");
+
+        Extractor::new().check_free_space(true).extract(&mut file, &dst).unwrap();
+
+        assert!(dst.join("file_1.txt").is_file());
+        fs::remove_dir_all(&dst).unwrap();
+    }
+}
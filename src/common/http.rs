@@ -0,0 +1,173 @@
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+
+/// Bytes requested at once by default, several headers with small entries fit into one request.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+fn http_error(e: ureq::Error) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Remote archive available via HTTP server with Range requests support.
+/// Reads are served from the last fetched chunk, so `HeadersParser` could walk headers
+/// of remote archive skipping entries data without downloading it.
+#[derive(Debug)]
+pub struct HttpSource {
+    agent: ureq::Agent,
+    url: String,
+    len: u64,
+    position: u64,
+    chunk_size: usize,
+    chunk_start: u64,
+    chunk: Vec<u8>,
+}
+
+impl HttpSource {
+    /// Archive length is requested by HEAD request, so server must provide `Content-Length`.
+    pub fn from(url: &str) -> io::Result<HttpSource> {
+        let agent = ureq::Agent::new();
+        let response = agent.head(url).call().map_err(http_error)?;
+        let len = response
+            .header("Content-Length")
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "server did not send content length"))?;
+        Ok(HttpSource {
+            agent,
+            url: url.to_string(),
+            len,
+            position: 0,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            chunk_start: 0,
+            chunk: Vec::new(),
+        })
+    }
+
+    /// Bytes requested at once (64 KiB by default), smaller chunks mean more requests.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = size.max(1);
+        self
+    }
+
+    /// Remote archive size.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Fetch chunk starting at current position.
+    fn fetch(&mut self) -> io::Result<()> {
+        let end = self.len.min(self.position + self.chunk_size as u64);
+        let range = format!("bytes={}-{}", self.position, end - 1);
+        let response = self.agent.get(&self.url).set("Range", &range).call().map_err(http_error)?;
+        if response.status() != 206 {
+            return Err(io::Error::new(ErrorKind::Unsupported, "server does not support range requests"));
+        }
+        let mut chunk = Vec::with_capacity((end - self.position) as usize);
+        response.into_reader().read_to_end(&mut chunk)?;
+        self.chunk_start = self.position;
+        self.chunk = chunk;
+        Ok(())
+    }
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+        let chunk_end = self.chunk_start + self.chunk.len() as u64;
+        if self.position < self.chunk_start || self.position >= chunk_end {
+            self.fetch()?;
+        }
+        let start = (self.position - self.chunk_start) as usize;
+        let n = buf.len().min(self.chunk.len() - start);
+        buf[..n].copy_from_slice(&self.chunk[start..start + n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+/// Seeking is free, data is requested only by the next read.
+impl Seek for HttpSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "invalid seek to a negative position"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use hamcrest2::prelude::*;
+
+    use super::*;
+    use super::super::meta::Header;
+    use super::super::read::{read_data_string, HeadersParser};
+    use super::super::testutil::test_resources_path;
+
+    /// Serve file over HTTP with Range support, returns server address.
+    fn serve(data: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut range = None;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.trim().to_ascii_lowercase().strip_prefix("range: bytes=") {
+                        let (start, end) = value.split_once('-').unwrap();
+                        range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+                    }
+                }
+                let response = match range {
+                    Some((start, end)) => {
+                        let body = &data[start..=end.min(data.len() - 1)];
+                        let mut response = format!(
+                            "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .into_bytes();
+                        response.extend_from_slice(body);
+                        response
+                    }
+                    None => format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", data.len()).into_bytes(),
+                };
+                stream.write_all(&response).unwrap();
+            }
+        });
+        format!("http://{}/files_test.tar", address)
+    }
+
+    #[test]
+    fn remote_headers() {
+        let data = std::fs::read(test_resources_path().join("files_test.tar")).unwrap();
+        let url = serve(data.clone());
+
+        let mut source = HttpSource::from(&url).unwrap().chunk_size(1024);
+        assert_that!(source.len(), equal_to(data.len() as u64));
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        let names = headers.iter().map(|h| h.name.as_str()).collect::<Vec<&str>>();
+        assert_that!(names, equal_to(vec!["file_1.txt", "file_2.txt", "file_3_dir/file_3.txt", "file_4.txt"]));
+
+        let text = read_data_string(&mut source, &headers[1]).unwrap();
+        assert_that!(text.len(), equal_to(15));
+    }
+}
@@ -2,20 +2,33 @@
 // https://www.gnu.org/software/tar/manual/html_node/Standard.html
 // https://www.ibm.com/support/knowledgecenter/en/SSLTBW_2.1.0/com.ibm.zos.v2r1.bpxa500/taf.htm
 use core::ops::Range;
-use std::collections::HashMap;
-use std::io::{Read, Seek, SeekFrom};
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use super::{BLOCK_SIZE, offset_by_blocks, pair_match_key, pair_match_value, parse_isize, parse_usize};
+use crate::TarError;
+
+use super::pax::{decode_base64, parse_signed_decimal};
+use super::read::{entry_reader, HeadersParser};
+use super::{crc32_update, offset_by_blocks, pair_match_key, pair_match_value, parse_decimal_field, parse_field, parse_field_lenient, parse_numeric, parse_octal, parse_usize, BLOCK_SIZE};
 
 pub const HEADER_SIZE: usize = 500;
 
 const ASCII_SPACE: u8 = 32;
-// Last char also could be \0
-const HEADER_MAGIC: &'static [u8; 6] = b"ustar ";
-const HEADER_VERSION: &'static [u8; 2] = b"00";
+/// POSIX magic value
+const HEADER_MAGIC: &[u8; 6] = b"ustar\0";
+/// Old GNU format uses space instead of \0 as last char
+const HEADER_MAGIC_GNU: &[u8; 6] = b"ustar ";
+/// Version values seen in the wild: POSIX `00`, old GNU ` \0` and `  ` written by some tools.
+const HEADER_VERSIONS: [&[u8; 2]; 3] = [b"00", b" \0", b"  "];
 
 /// Checksum header validation status.
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HeaderCheck {
     Valid,
     /// Represents invalid header. All non ustar headers are also considered as invalid.
@@ -25,8 +38,99 @@ pub enum HeaderCheck {
     Zeroes,
 }
 
+/// Details of `HeaderCheck::Invalid` status.
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InvalidReason {
+    /// Checksum field is empty or contains characters other than octal digits, spaces and NULs.
+    MalformedChecksum,
+    /// Stored checksum matches neither unsigned nor signed sum of header bytes.
+    ChecksumMismatch,
+    /// Checksum is fine but magic is neither POSIX nor GNU one.
+    NotUstar,
+}
+
+/// Checksum convention header was validated with.
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChecksumKind {
+    /// Bytes summed as signed chars (old Sun and NeXT tar).
+    Signed,
+    /// Bytes summed as unsigned chars (POSIX).
+    Unsigned,
+    /// Both sums are equal (no bytes with high bit set).
+    Both,
+}
+
+/// Structural problem of header that is still parseable.
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HeaderIssue {
+    /// Directory or link declares non zero size (corruption or GNU dumpdir extension).
+    UnexpectedSize,
+    /// Name was already used by previous entry (fine for appended archives, suspicious for fresh ones).
+    DuplicateName,
+    /// Source ended before the end of entry data (only part of metadata could be used).
+    TruncatedData,
+    /// Version field is none of known variants (`00`, ` \0`, `  `).
+    UnknownVersion,
+    /// Text field contains control bytes.
+    Unprintable(TextField),
+    /// Text field fills its whole width without NUL, for name it usually means GNU long name
+    /// or corruption (fine only if the path continues in ustar prefix field).
+    Unterminated(TextField),
+}
+
+/// Header text fields checked by `PosixHeader::structure_issues`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TextField {
+    Name,
+    Uname,
+    Gname,
+}
+
+/// Reason why numeric header field could not be read.
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FieldError {
+    /// Value does not fit into result type.
+    Overflow,
+    /// Field has no digits or contains unexpected characters.
+    Malformed,
+}
+
+/// Result of strict numeric field parsing.
+pub type NumericField = Result<u64, FieldError>;
+
+/// Defines which headers are accepted by parser depending on validation status.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum ChecksumPolicy {
+    /// Header must have valid checksum and ustar magic.
+    #[default]
+    Strict,
+    /// Header must have valid checksum, magic is ignored (old v7 archives has no magic).
+    ChecksumOnly,
+    /// Accept any non zero header that looks structurally plausible.
+    None,
+}
+
+impl ChecksumPolicy {
+    /// Check if header should be accepted according to this policy.
+    pub fn accepts(&self, header: &PosixHeader) -> bool {
+        match (self, header.check) {
+            (_, HeaderCheck::Zeroes) => false,
+            (_, HeaderCheck::Valid) => true,
+            (ChecksumPolicy::Strict, _) => false,
+            (ChecksumPolicy::ChecksumOnly, HeaderCheck::Invalid { not_ustar }) => not_ustar,
+            (ChecksumPolicy::None, _) => header.is_plausible(),
+        }
+    }
+}
+
 /// POSIX header: tar Header Block, from POSIX 1003.1-1990.
 /// This is just wrapper around raw bytes array.
+#[derive(Clone)]
 pub struct PosixHeader {
     offset: usize,
     check: HeaderCheck,
@@ -45,36 +149,37 @@ pub struct Mode;
 //Bits used in the mode field, values in octal.
 impl Mode {
     /// set UID on execution
-    pub const TSUID: u16 = 0x04000;
+    pub const TSUID: u16 = 0o4000;
     /// set GID on execution
-    pub const TSGID: u16 = 0x02000;
+    pub const TSGID: u16 = 0o2000;
     /// reserved
-    pub const TSVTX: u16 = 0x01000;
+    pub const TSVTX: u16 = 0o1000;
     // file permissions
     /// read by owner
-    pub const TUREAD: u16 = 0x00400;
+    pub const TUREAD: u16 = 0o0400;
     /// write by owner
-    pub const TUWRITE: u16 = 0x00200;
+    pub const TUWRITE: u16 = 0o0200;
     /// execute/search by owner
-    pub const TUEXEC: u16 = 0x00100;
+    pub const TUEXEC: u16 = 0o0100;
     /// read by group
-    pub const TGREAD: u16 = 0x00040;
+    pub const TGREAD: u16 = 0o0040;
     /// write by group
-    pub const TGWRITE: u16 = 0x00020;
+    pub const TGWRITE: u16 = 0o0020;
     /// execute/search by group
-    pub const TGEXEC: u16 = 0x00010;
+    pub const TGEXEC: u16 = 0o0010;
     /// read by other
-    pub const TOREAD: u16 = 0x00004;
+    pub const TOREAD: u16 = 0o0004;
     /// write by other
-    pub const TOWRITE: u16 = 0x00002;
+    pub const TOWRITE: u16 = 0o0002;
     /// execute/search by other
-    pub const TOEXEC: u16 = 0x00001;
+    pub const TOEXEC: u16 = 0o0001;
 }
 
 /// Offsets are here: https://www.gnu.org/software/tar/manual/html_node/Standard.html
 #[non_exhaustive]
 pub struct HeaderProperty;
 
+#[allow(non_upper_case_globals)]
 impl HeaderProperty {
     pub const Name: Range<usize> = 0..100;
     pub const Mode: Range<usize> = 100..108;
@@ -92,10 +197,66 @@ impl HeaderProperty {
     pub const Devmajor: Range<usize> = 329..337;
     pub const Devminor: Range<usize> = 337..345;
     pub const Prefix: Range<usize> = 345..500;
+    /// Access time, old GNU format only (same area as prefix)
+    pub const GnuAtime: Range<usize> = 345..357;
+    /// Change time, old GNU format only (same area as prefix)
+    pub const GnuCtime: Range<usize> = 357..369;
+}
+
+/// On-disk layout of header block, see `PosixHeader::as_raw`.
+#[cfg(feature = "bytemuck")]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct RawUstarHeader {
+    pub name: [u8; 100],
+    pub mode: [u8; 8],
+    pub uid: [u8; 8],
+    pub gid: [u8; 8],
+    pub size: [u8; 12],
+    pub mtime: [u8; 12],
+    pub chksum: [u8; 8],
+    pub typeflag: u8,
+    pub linkname: [u8; 100],
+    pub magic: [u8; 6],
+    pub version: [u8; 2],
+    pub uname: [u8; 32],
+    pub gname: [u8; 32],
+    pub devmajor: [u8; 8],
+    pub devminor: [u8; 8],
+    pub prefix: [u8; 155],
+    pub pad: [u8; 12],
+}
+
+/// POSIX ustar fields in header order.
+const FIELD_RANGES: [(&str, Range<usize>); 16] = [
+    ("name", HeaderProperty::Name),
+    ("mode", HeaderProperty::Mode),
+    ("uid", HeaderProperty::Uid),
+    ("gid", HeaderProperty::Gid),
+    ("size", HeaderProperty::Size),
+    ("mtime", HeaderProperty::Mtime),
+    ("chksum", HeaderProperty::Chksum),
+    ("typeflag", HeaderProperty::Typeflag),
+    ("linkname", HeaderProperty::Linkname),
+    ("magic", HeaderProperty::Magic),
+    ("version", HeaderProperty::Version),
+    ("uname", HeaderProperty::Uname),
+    ("gname", HeaderProperty::Gname),
+    ("devmajor", HeaderProperty::Devmajor),
+    ("devminor", HeaderProperty::Devminor),
+    ("prefix", HeaderProperty::Prefix),
+];
+
+impl HeaderProperty {
+    /// Names and byte ranges of all POSIX ustar header fields (e.g. for external verifiers).
+    pub fn field_ranges() -> &'static [(&'static str, Range<usize>)] {
+        &FIELD_RANGES
+    }
 }
 
 /// Type of header related to typecalss property in POSIX spec.
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum HeaderType {
     /// regular file
     Reg,
@@ -117,10 +278,51 @@ pub enum HeaderType {
     Xhd,
     /// Global extended header
     Xlg,
+    /// GNU long name for the next file in the archive
+    GnuLongName,
+    /// GNU long link name for the next file in the archive
+    GnuLongLink,
+    /// GNU incremental dump directory, data lists directory content
+    Dumpdir,
+    /// GNU continuation of file started in previous volume of multi-volume archive
+    GnuMultiVolume,
+    /// GNU volume label
+    GnuVolumeLabel,
     Unknown,
 }
 
-const TYPE_FLAGS: [(HeaderType, u8); 11] = [
+impl HeaderType {
+    /// Typeflag byte as it is stored in header.
+    pub fn flag(&self) -> Option<u8> {
+        pair_match_key(*self, &TYPE_FLAGS)
+    }
+
+    /// Entries that represent file system objects (files, directories, links, devices).
+    /// Metadata, dumpdir and multi-volume pseudo entries are not extractable.
+    pub fn is_extractable(&self) -> bool {
+        matches!(
+            self,
+            HeaderType::Reg
+                | HeaderType::Link
+                | HeaderType::Sym
+                | HeaderType::Chr
+                | HeaderType::Blk
+                | HeaderType::Dir
+                | HeaderType::Fifo
+                | HeaderType::Cont
+        )
+    }
+
+    /// Pseudo entries that only carry metadata for other entries.
+    pub fn is_metadata(&self) -> bool {
+        matches!(
+            self,
+            HeaderType::Xhd | HeaderType::Xlg | HeaderType::GnuLongName | HeaderType::GnuLongLink
+        )
+    }
+}
+
+const TYPE_FLAGS: [(HeaderType, u8); 16] = [
     (HeaderType::Reg, b'0'),
     (HeaderType::Link, b'1'),
     (HeaderType::Sym, b'2'),
@@ -131,68 +333,343 @@ const TYPE_FLAGS: [(HeaderType, u8); 11] = [
     (HeaderType::Cont, b'7'),
     (HeaderType::Xhd, b'x'),
     (HeaderType::Xlg, b'g'),
+    (HeaderType::GnuLongName, b'L'),
+    (HeaderType::GnuLongLink, b'K'),
+    (HeaderType::Dumpdir, b'D'),
+    (HeaderType::GnuMultiVolume, b'M'),
+    (HeaderType::GnuVolumeLabel, b'V'),
     // Duplicate matcher for old format
     (HeaderType::Reg, b'\0'),
 ];
 
 
 /// Contains Rust friendly representation from POSIX header raw content.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Header {
     pub check: HeaderCheck,
-    /// Header position in source
+    /// Header position in source (start of the header block)
     pub offset: usize,
     /// Index of previous revision (related to headers order in source)
     pub prev: Option<usize>,
 
     pub typeflag: HeaderType,
 
+    /// Full path (prefix field joined with name field for POSIX ustar)
     pub name: String,
-    // Prefix // :)
+    /// Raw ustar prefix field, empty for other formats (GNU stores times there)
+    pub prefix: String,
     pub linkname: String,
     pub uname: String,
     pub gname: String,
+    pub uid: u64,
+    pub gid: u64,
     pub mode: u64,
     // char[12]
     pub mtime: u128,
+    /// Access time from PAX record or old GNU header
+    pub atime: Option<u128>,
+    /// Status change time from PAX record or old GNU header
+    pub ctime: Option<u128>,
     // char[12]
     pub size: usize,
+    /// PAX records (global and extended) applied to this header
+    pub pax_records: Vec<(String, Vec<u8>)>,
+    /// Structural problems found by parser in strict mode
+    pub issues: Vec<HeaderIssue>,
+    /// Status and name pairs from GNU dumpdir entry data (empty for other types)
+    pub dumpdir: Vec<(char, String)>,
+    /// Size field as parsed (or overridden by lenient parser), PAX record is checked separately
+    pub(crate) size_field: NumericField,
+    /// Mtime field as parsed (or overridden by lenient parser), PAX record is checked separately
+    pub(crate) mtime_field: NumericField,
+    /// Raw header block as it was read
+    #[cfg_attr(feature = "serde", serde(with = "raw_block"))]
+    raw: PosixHeader,
+}
+
+/// Raw header block is serialized as plain bytes.
+#[cfg(feature = "serde")]
+mod raw_block {
+    use std::convert::TryFrom;
+
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{PosixHeader, BLOCK_SIZE};
+
+    pub fn serialize<S: Serializer>(raw: &PosixHeader, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&raw.buffer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PosixHeader, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        let buffer = <[u8; BLOCK_SIZE]>::try_from(bytes.as_slice())
+            .map_err(|_| D::Error::invalid_length(bytes.len(), &"header block"))?;
+        Ok(PosixHeader::from(0, buffer))
+    }
+}
+
+/// Direction of sorting.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+/// Header property used by `TarMeta::sorted_by`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum SortKey {
+    Name(SortOrder),
+    Size(SortOrder),
+    Mtime(SortOrder),
+}
+
+/// Text formats for archive listing.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ManifestFormat {
+    /// Comma separated values with header row.
+    Csv,
+    /// JSON array with object per entry.
+    #[cfg(feature = "serde")]
+    Json,
 }
 
 /// Aggregate meta info about tar archive (combine all headers in easy accessible way).
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TarMeta {
     /// List of haders in same order as in source
     headers: Vec<Header>,
 
     /// Headers index by file name
     index: HashMap<String, usize>,
+
+    /// Latest header index by case folded name, built by the first `get_ci` call
+    #[cfg_attr(feature = "serde", serde(skip))]
+    folded: OnceLock<HashMap<String, usize>>,
+}
+
+/// Name for case insensitive comparison: lower case without leading `./` and trailing slash.
+fn fold_case(name: &str) -> String {
+    let mut name = name;
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest;
+    }
+    name.trim_end_matches('/').to_lowercase()
 }
 
 impl Header {
     pub fn from(pheader: PosixHeader) -> Header {
+        Header::from_decoded(pheader, |b| String::from_utf8_lossy(b).into_owned())
+    }
+
+    /// Same as `from` but names are decoded with specified encoding instead of UTF-8.
+    #[cfg(feature = "encoding")]
+    pub fn from_encoded(pheader: PosixHeader, encoding: &'static encoding_rs::Encoding) -> Header {
+        Header::from_decoded(pheader, |b| encoding.decode_without_bom_handling(b).0.into_owned())
+    }
+
+    fn from_decoded<F: Fn(&[u8]) -> String>(pheader: PosixHeader, decode: F) -> Header {
         Header {
             offset: pheader.offset,
-            check: pheader.check.clone(),
+            check: pheader.check,
             prev: None,
 
             size: pheader.size(),
             typeflag: pheader.typeflag(),
 
-            name: String::new(),
-            linkname: String::new(),
-            uname: String::new(),
-            gname: String::new(),
-            mode: 0,
-            mtime: 0,
+            name: pheader.name_decoded(&decode),
+            prefix: match pheader.extract(HeaderProperty::Magic) {
+                magic if magic == HEADER_MAGIC => decode(pheader.extract_terminated(HeaderProperty::Prefix)),
+                _ => String::new(),
+            },
+            linkname: decode(pheader.extract_terminated(HeaderProperty::Linkname)),
+            uname: decode(pheader.extract_terminated(HeaderProperty::Uname)),
+            gname: decode(pheader.extract_terminated(HeaderProperty::Gname)),
+            uid: pheader.uid(),
+            gid: pheader.gid(),
+            mode: pheader.mode(),
+            mtime: pheader.mtime(),
+            atime: pheader.gnu_time(HeaderProperty::GnuAtime),
+            ctime: pheader.gnu_time(HeaderProperty::GnuCtime),
+            pax_records: Vec::new(),
+            issues: Vec::new(),
+            dumpdir: Vec::new(),
+            size_field: pheader.numeric_field(HeaderProperty::Size),
+            mtime_field: pheader.numeric_field(HeaderProperty::Mtime),
+            raw: pheader,
+        }
+    }
+
+    /// Magic field bytes as they are in header block (`ustar\0` for POSIX, `ustar ` for GNU).
+    pub fn magic(&self) -> [u8; 6] {
+        let mut magic = [0; 6];
+        magic.copy_from_slice(self.raw.extract(HeaderProperty::Magic));
+        magic
+    }
+
+    /// Typeflag byte as it is in header block, also for types not known by `HeaderType`.
+    pub fn typeflag_byte(&self) -> u8 {
+        self.raw.extract(HeaderProperty::Typeflag)[0]
+    }
+
+    /// Version field bytes as they are in header block (`00` for POSIX, ` \0` for GNU).
+    pub fn version(&self) -> [u8; 2] {
+        let mut version = [0; 2];
+        version.copy_from_slice(self.raw.version());
+        version
+    }
+
+    /// Modification time in seconds related to epoch, unlike `mtime` dates before 1970 are negative.
+    /// PAX `mtime` record overrides header field.
+    pub fn mtime_signed(&self) -> i128 {
+        match self.pax_record("mtime").and_then(parse_signed_decimal) {
+            Some(mtime) => mtime,
+            None => match self.mtime_field {
+                Ok(mtime) => mtime as i128,
+                // Negative base-256 values are not valid for unsigned field
+                Err(_) => self.raw.mtime_signed(),
+            },
+        }
+    }
+
+    /// Modification time as system time, None if it could not be represented.
+    pub fn modified(&self) -> Option<SystemTime> {
+        let mtime = self.mtime_signed();
+        let secs = Duration::from_secs(mtime.unsigned_abs().try_into().ok()?);
+        if mtime < 0 {
+            UNIX_EPOCH.checked_sub(secs)
+        } else {
+            UNIX_EPOCH.checked_add(secs)
+        }
+    }
+
+    /// Data size like `size` but malformed or overflowing field is reported as error.
+    /// PAX `size` record overrides header field.
+    pub fn size_checked(&self) -> NumericField {
+        match self.pax_record("size") {
+            Some(value) => parse_decimal_field(value),
+            None => self.size_field,
+        }
+    }
+
+    /// Modification time like `mtime` but malformed or overflowing field is reported as error.
+    pub fn mtime_checked(&self) -> NumericField {
+        match self.pax_record("mtime") {
+            Some(value) => parse_decimal_field(value.split(|&b| b == b'.').next().unwrap_or_default()),
+            None => self.mtime_field,
+        }
+    }
+
+    /// Zero bytes added after entry data to fill the last block.
+    pub fn padding(&self) -> usize {
+        offset_by_blocks(self.size) - self.size
+    }
+
+    /// Value of PAX record by key (extended header record overrides global one).
+    pub fn pax_record(&self, key: &str) -> Option<&[u8]> {
+        self.pax_records.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v.as_slice())
+    }
+
+    /// Extended attributes from PAX records.
+    /// `SCHILY.xattr.` values are raw bytes, `LIBARCHIVE.xattr.` ones are base64 encoded.
+    pub fn xattrs(&self) -> HashMap<String, Vec<u8>> {
+        let mut xattrs = HashMap::new();
+        for (key, value) in &self.pax_records {
+            if let Some(name) = key.strip_prefix("SCHILY.xattr.") {
+                xattrs.insert(name.to_string(), value.clone());
+            } else if let Some(name) = key.strip_prefix("LIBARCHIVE.xattr.") {
+                if let Some(decoded) = decode_base64(value) {
+                    xattrs.insert(name.to_string(), decoded);
+                }
+            }
+        }
+        xattrs
+    }
+
+    /// Header type with fallback for archives that don't set typeflag properly.
+    ///
+    /// Heuristic is applied only for unknown typeflag (or regular file from old format):
+    /// name with trailing slash is a directory (old tar convention),
+    /// unknown type with non empty linkname is considered as a symbolic link.
+    pub fn effective_type(&self) -> HeaderType {
+        match self.typeflag {
+            HeaderType::Unknown | HeaderType::Reg if self.name.ends_with('/') => HeaderType::Dir,
+            HeaderType::Unknown if !self.linkname.is_empty() => HeaderType::Sym,
+            t => t,
         }
     }
+
+    /// CRC-32 (the one used by zip and gzip) of entry data, cheap alternative to SHA-256 for comparisons.
+    pub fn crc32<S: Read + Seek>(&self, source: &mut S) -> io::Result<u32> {
+        let mut reader = entry_reader(source, self)?;
+        let mut buffer = [0; 8 * BLOCK_SIZE];
+        let mut crc = 0;
+        loop {
+            match reader.read(&mut buffer)? {
+                0 => return Ok(crc),
+                n => crc = crc32_update(crc, &buffer[..n]),
+            }
+        }
+    }
+
+    /// `xxd` style hex and ASCII view of up to `max_bytes` first bytes of entry data.
+    pub fn hexdump<S: Read + Seek>(&self, source: &mut S, max_bytes: usize) -> io::Result<String> {
+        let data = self.peek_data(source, max_bytes)?;
+        let mut dump = String::new();
+        for (i, line) in data.chunks(16).enumerate() {
+            let hex = line
+                .chunks(2)
+                .map(|pair| pair.iter().map(|b| format!("{:02x}", b)).collect::<String>())
+                .collect::<Vec<String>>()
+                .join(" ");
+            let text = line
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+                .collect::<String>();
+            dump.push_str(&format!("{:08x}: {:<39}  {}\n", i * 16, hex, text));
+        }
+        Ok(dump)
+    }
+
+    /// Device (major, minor) numbers of character and block device entries.
+    pub fn device(&self) -> Option<(u32, u32)> {
+        if !matches!(self.typeflag, HeaderType::Chr | HeaderType::Blk) {
+            return None;
+        }
+        let number = |range| self.raw.numeric_field(range).ok()?.try_into().ok();
+        Some((number(HeaderProperty::Devmajor)?, number(HeaderProperty::Devminor)?))
+    }
+
+    /// Single gate for extractors, see `HeaderType::is_extractable`.
+    /// Heuristics of `effective_type` are applied for unknown types.
+    pub fn is_extractable(&self) -> bool {
+        self.effective_type().is_extractable()
+    }
+
+    /// Position of the header block start in source.
+    /// Entry data starts right after it at `header_offset() + BLOCK_SIZE`.
+    pub fn header_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Read up to `n` first bytes of entry data (e.g. to sniff file type by magic bytes).
+    /// Source position is restored afterwards.
+    pub fn peek_data<S: Read + Seek>(&self, source: &mut S, n: usize) -> io::Result<Vec<u8>> {
+        let position = source.stream_position()?;
+        source.seek(SeekFrom::Start((self.offset + BLOCK_SIZE) as u64))?;
+        let mut data = vec![0; n.min(self.size)];
+        let read = source.read_exact(&mut data);
+        source.seek(SeekFrom::Start(position))?;
+        read.map(|_| data)
+    }
 }
 
 impl PosixHeader {
     pub fn from(offset: usize, bytes: [u8; BLOCK_SIZE]) -> PosixHeader {
         let mut ph = PosixHeader {
-            offset: offset,
+            offset,
             buffer: bytes,
             check: HeaderCheck::Invalid { not_ustar: false },
         };
@@ -200,86 +677,1196 @@ impl PosixHeader {
         ph
     }
 
+    /// Header block as typed struct without copying.
+    #[cfg(feature = "bytemuck")]
+    pub fn as_raw(&self) -> &RawUstarHeader {
+        bytemuck::from_bytes(&self.buffer)
+    }
+
+    /// Validation status calculated on creation.
+    pub fn check(&self) -> HeaderCheck {
+        self.check
+    }
+
+    /// Data size, octal or base-256 encoded (used by GNU tar for files of 8 GiB and larger).
+    /// Malformed or overflowing field gives 0, see `numeric_field` for details.
     pub fn size(&self) -> usize {
+        let raw = self.extract(HeaderProperty::Size);
+        if raw[0] & 0x80 != 0 {
+            return parse_field(raw).ok().and_then(|v| usize::try_from(v).ok()).unwrap_or_default();
+        }
         let size_str = self.extract_string(HeaderProperty::Size);
         parse_usize(&size_str).unwrap_or_default()
     }
 
+    /// File name joined with prefix field for POSIX ustar headers.
+    /// Old GNU format uses prefix area for other data so it is not used there.
+    pub fn name(&self) -> String {
+        self.name_decoded(|b| String::from_utf8_lossy(b).into_owned())
+    }
+
+    fn name_decoded<F: Fn(&[u8]) -> String>(&self, decode: F) -> String {
+        let name = decode(self.extract_terminated(HeaderProperty::Name));
+        if self.extract(HeaderProperty::Magic) != HEADER_MAGIC {
+            return name;
+        }
+        let prefix = decode(self.extract_terminated(HeaderProperty::Prefix));
+        if prefix.is_empty() {
+            name
+        } else {
+            format!("{}/{}", prefix, name)
+        }
+    }
+
+    pub fn mode(&self) -> u64 {
+        parse_octal(self.extract(HeaderProperty::Mode)).unwrap_or_default() as u64
+    }
+
+    pub fn uid(&self) -> u64 {
+        parse_numeric(self.extract(HeaderProperty::Uid)).unwrap_or_default().max(0) as u64
+    }
+
+    pub fn gid(&self) -> u64 {
+        parse_numeric(self.extract(HeaderProperty::Gid)).unwrap_or_default().max(0) as u64
+    }
+
+    /// Numeric field (octal or base-256) with detailed error instead of lossy 0.
+    pub fn numeric_field(&self, range: Range<usize>) -> NumericField {
+        parse_field(self.extract(range))
+    }
+
+    /// Numeric field from the longest leading octal run, junk after it is ignored.
+    pub fn numeric_field_lenient(&self, range: Range<usize>) -> NumericField {
+        parse_field_lenient(self.extract(range))
+    }
+
+    /// Modification time, dates before epoch (negative base-256 values) are reported as 0.
+    pub fn mtime(&self) -> u128 {
+        self.mtime_signed().max(0) as u128
+    }
+
+    /// Modification time in seconds related to epoch, negative for older dates.
+    pub fn mtime_signed(&self) -> i128 {
+        parse_numeric(self.extract(HeaderProperty::Mtime)).unwrap_or_default()
+    }
+
+    /// Access or change time stored by old GNU format, None if not set or magic is not GNU.
+    fn gnu_time(&self, range: Range<usize>) -> Option<u128> {
+        if self.extract(HeaderProperty::Magic) != HEADER_MAGIC_GNU {
+            return None;
+        }
+        match parse_numeric(self.extract(range))? {
+            t if t > 0 => Some(t as u128),
+            _ => None,
+        }
+    }
+
     pub fn typeflag(&self) -> HeaderType {
         let flag = self.extract(HeaderProperty::Typeflag)[0];
         pair_match_value(flag, &TYPE_FLAGS).unwrap_or(HeaderType::Unknown)
     }
 
+    /// Check header structure beyond checksum.
+    pub fn structure_issues(&self) -> Vec<HeaderIssue> {
+        let mut issues = Vec::new();
+        let no_data = matches!(self.typeflag(), HeaderType::Dir | HeaderType::Sym | HeaderType::Link);
+        if no_data && self.size() > 0 {
+            issues.push(HeaderIssue::UnexpectedSize);
+        }
+        if !self.version_known() {
+            issues.push(HeaderIssue::UnknownVersion);
+        }
+        let fields = [
+            (TextField::Name, HeaderProperty::Name),
+            (TextField::Uname, HeaderProperty::Uname),
+            (TextField::Gname, HeaderProperty::Gname),
+        ];
+        for (field, range) in fields {
+            if self.extract_terminated(range.clone()).iter().any(u8::is_ascii_control) {
+                issues.push(HeaderIssue::Unprintable(field));
+            }
+            if !self.extract(range).contains(&0) && !self.name_continues_in_prefix(field) {
+                issues.push(HeaderIssue::Unterminated(field));
+            }
+        }
+        issues
+    }
+
+    /// Full width name is legal for POSIX ustar if the rest of path is in prefix field.
+    fn name_continues_in_prefix(&self, field: TextField) -> bool {
+        field == TextField::Name
+            && self.extract(HeaderProperty::Magic) == HEADER_MAGIC
+            && !self.extract_terminated(HeaderProperty::Prefix).is_empty()
+    }
+
+    /// Raw version field bytes.
+    pub fn version(&self) -> &[u8] {
+        self.extract(HeaderProperty::Version)
+    }
+
+    /// Version is one of known variants, any variant is accepted with any ustar magic.
+    pub fn version_known(&self) -> bool {
+        HEADER_VERSIONS.iter().any(|&v| v == self.version())
+    }
+
     /// Extract property from raw buffer as it is.
     pub fn extract(&self, bytes_range: Range<usize>) -> &[u8] {
         &self.buffer[bytes_range]
     }
 
-    pub fn extract_string(&self, bytes_range: Range<usize>) -> String {
+    /// Extract property bytes till the first NUL.
+    pub fn extract_terminated(&self, bytes_range: Range<usize>) -> &[u8] {
         let v = self.extract(bytes_range);
-        let mut range = 0..v.len();
-        for i in 0..v.len() {
-            if v[i] == 0 {
-                range = 0..i;
-                break;
-            }
+        let end = v.iter().position(|&b| b == 0).unwrap_or(v.len());
+        &v[..end]
+    }
+
+    pub fn extract_string(&self, bytes_range: Range<usize>) -> String {
+        String::from_utf8_lossy(self.extract_terminated(bytes_range)).into_owned()
+    }
+
+    /// Check that header looks like a header even if checksum is wrong.
+    /// Block should not be empty and numeric fields should be parseable.
+    pub fn is_plausible(&self) -> bool {
+        if self.check == HeaderCheck::Zeroes {
+            return false;
         }
+        let size = self.extract_string(HeaderProperty::Size);
+        let mode = self.extract_string(HeaderProperty::Mode);
+        parse_usize(size.trim()).is_ok() && parse_usize(mode.trim()).is_ok()
+    }
 
-        String::from_utf8_lossy(&v[range]).into_owned()
+    /// Raw header block as it is (with all modifications).
+    pub fn to_bytes(&self) -> [u8; BLOCK_SIZE] {
+        self.buffer
     }
 
-    /// Does header checksum validation
-    ///
-    /// The standard BSD tar sources create the checksum by adding up the bytes in the header as type char.
-    /// It looks like the sources to BSD tar were never changed to compute the checksum correctly,
-    /// so both the Sun and Next add the bytes of the header as signed chars.
-    /// This doesn't cause a problem until you get a file with a name containing characters with the high bit set.
-    /// So tar_checksum computes two checksums -- signed and unsigned.
-    pub fn validate(&self) -> HeaderCheck {
+    /// Write value to the header field, rest of the field is filled with zeroes.
+    /// Value is truncated if it does not fit into field.
+    /// Validation status is refreshed, use `repair_checksum` to make header valid again.
+    pub fn set_field(&mut self, bytes_range: Range<usize>, value: &[u8]) {
+        let field = &mut self.buffer[bytes_range];
+        let len = value.len().min(field.len());
+        field[..len].copy_from_slice(&value[..len]);
+        for b in field[len..].iter_mut() {
+            *b = 0;
+        }
+        self.check = self.validate();
+    }
+
+    /// Recalculate and write checksum (the POSIX unsigned one) for current header content.
+    pub fn repair_checksum(&mut self) {
+        let (unsigned_sum, _) = self.checksums();
+        let checksum = format!("{:06o}\0 ", unsigned_sum);
+        self.buffer[HeaderProperty::Chksum].copy_from_slice(checksum.as_bytes());
+        self.check = self.validate();
+    }
+
+    /// Checksum value stored in header.
+    /// Field is usually six octal digits followed by NUL and space,
+    /// but other orders of trailing spaces and NULs are accepted too.
+    /// Any other characters (even after NUL) make field malformed.
+    pub fn checksum(&self) -> Option<usize> {
+        let field = self.extract(HeaderProperty::Chksum);
+        let start = field.iter().take_while(|&&b| b == b' ').count();
+        let end = start + field[start..].iter().take_while(|&&b| (b'0'..=b'7').contains(&b)).count();
+        if field[end..].iter().any(|&b| b != b' ' && b != 0) {
+            return None;
+        }
+        parse_octal(field)
+    }
+
+    /// Why header is not valid, None for valid and zero headers.
+    pub fn invalid_reason(&self) -> Option<InvalidReason> {
+        if self.buffer[..HEADER_SIZE].iter().all(|&b| b == 0) {
+            return None;
+        }
+        let checksum = match self.checksum() {
+            Some(v) => v,
+            None => return Some(InvalidReason::MalformedChecksum),
+        };
+        let (unsigned_sum, signed_sum) = self.checksums();
+        if unsigned_sum != checksum && signed_sum != checksum as isize {
+            return Some(InvalidReason::ChecksumMismatch);
+        }
+        let magic = self.extract(HeaderProperty::Magic);
+        if magic == HEADER_MAGIC || magic == HEADER_MAGIC_GNU {
+            None
+        } else {
+            Some(InvalidReason::NotUstar)
+        }
+    }
+
+    /// Which sum matches stored checksum, None if neither does or checksum field is malformed.
+    pub fn checksum_kind(&self) -> Option<ChecksumKind> {
+        let checksum = self.checksum()?;
+        let (unsigned_sum, signed_sum) = self.checksums();
+        match (unsigned_sum == checksum, signed_sum == checksum as isize) {
+            (true, true) => Some(ChecksumKind::Both),
+            (true, false) => Some(ChecksumKind::Unsigned),
+            (false, true) => Some(ChecksumKind::Signed),
+            (false, false) => None,
+        }
+    }
+
+    /// Calculate unsigned and signed checksums assuming checksum field is all blanks.
+    fn checksums(&self) -> (usize, isize) {
         let mut unsigned_sum = 0_usize; // the POSIX one :-)
         let mut signed_sum = 0_isize; // the Sun one :-(
         let rchecksum = HeaderProperty::Chksum;
-        let mut zeroes = true;
 
         for i in 0..HEADER_SIZE {
             let mut value = self.buffer[i];
-            if value != 0 {
-                zeroes = false;
-            }
             if rchecksum.contains(&i) {
                 value = ASCII_SPACE;
             }
             unsigned_sum += value as usize;
             signed_sum += (value as i8) as isize;
         }
+        (unsigned_sum, signed_sum)
+    }
 
-        if zeroes {
+    /// Does header checksum validation
+    ///
+    /// The standard BSD tar sources create the checksum by adding up the bytes in the header as type char.
+    /// It looks like the sources to BSD tar were never changed to compute the checksum correctly,
+    /// so both the Sun and Next add the bytes of the header as signed chars.
+    /// This doesn't cause a problem until you get a file with a name containing characters with the high bit set.
+    /// So tar_checksum computes two checksums -- signed and unsigned.
+    pub fn validate(&self) -> HeaderCheck {
+        if self.buffer[..HEADER_SIZE].iter().all(|&b| b == 0) {
             return HeaderCheck::Zeroes;
         }
+        match self.invalid_reason() {
+            None => HeaderCheck::Valid,
+            Some(reason) => HeaderCheck::Invalid {
+                not_ustar: reason == InvalidReason::NotUstar,
+            },
+        }
+    }
+}
+
+impl From<&PosixHeader> for [u8; BLOCK_SIZE] {
+    fn from(header: &PosixHeader) -> Self {
+        header.to_bytes()
+    }
+}
+
+
+impl TarMeta {
+    /// Build meta from headers in the same order as in source.
+    /// Headers with the same name are linked via `prev` so the last one wins in index.
+    pub fn from<I: IntoIterator<Item = Header>>(headers: I) -> TarMeta {
+        let mut meta = TarMeta {
+            headers: Vec::new(),
+            index: HashMap::new(),
+            folded: OnceLock::new(),
+        };
+        for mut h in headers {
+            let idx = meta.headers.len();
+            h.prev = meta.index.insert(h.name.clone(), idx);
+            meta.headers.push(h);
+        }
+        meta
+    }
+
+    /// Open file and build meta for all its entries, see `from_reader`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> io::Result<TarMeta> {
+        let mut file = File::open(path)?;
+        TarMeta::from_reader(&mut file)
+    }
+
+    /// Build meta for all entries of source (read from the beginning) up to the terminator.
+    /// Unlike parser iterator, entries with invalid header are kept (see `invalid_entries`).
+    pub fn from_reader<S: Read + Seek>(source: &mut S) -> io::Result<TarMeta> {
+        source.rewind()?;
+        let mut parser = HeadersParser::from(source);
+        let mut headers = Vec::new();
+        while let Some((h, accepted)) = parser.next_any() {
+            match h.check {
+                HeaderCheck::Zeroes => break,
+                _ if accepted && h.typeflag.is_metadata() => {}
+                _ => headers.push(h),
+            }
+        }
+        Ok(TarMeta::from(headers))
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    /// Iterate over headers in the same order as in source.
+    pub fn iter(&self) -> std::slice::Iter<'_, Header> {
+        self.headers.iter()
+    }
+
+    /// Iterate over headers in reverse source order, the most recently appended first.
+    pub fn iter_rev(&self) -> std::iter::Rev<std::slice::Iter<'_, Header>> {
+        self.headers.iter().rev()
+    }
+
+    /// Latest header revision by file name.
+    /// Leading `./` and trailing slash are not required to match, e.g. `dir` finds `./dir/`.
+    pub fn get(&self, name: &str) -> Option<&Header> {
+        self.lookup(name).map(|i| &self.headers[i])
+    }
+
+    /// Check if there is entry with such name, names are matched the same way as in `get`.
+    pub fn contains(&self, name: &str) -> bool {
+        self.lookup(name).is_some()
+    }
+
+    /// Index of the latest revision, exact name first and then its normalized variants.
+    fn lookup(&self, name: &str) -> Option<usize> {
+        if let Some(&i) = self.index.get(name) {
+            return Some(i);
+        }
+        let mut name = name;
+        while let Some(rest) = name.strip_prefix("./") {
+            name = rest;
+        }
+        let name = name.trim_end_matches('/');
+        let variants = [
+            name.to_string(),
+            format!("{}/", name),
+            format!("./{}", name),
+            format!("./{}/", name),
+        ];
+        variants.iter().find_map(|n| self.index.get(n)).copied()
+    }
+
+    /// Same as `get` but names are compared case insensitively (as on Windows and macOS file systems),
+    /// exact match is preferred, otherwise the latest matching entry is returned.
+    pub fn get_ci(&self, name: &str) -> Option<&Header> {
+        if let Some(h) = self.get(name) {
+            return Some(h);
+        }
+        let folded = self.folded.get_or_init(|| {
+            let mut folded = HashMap::new();
+            for (name, &i) in &self.index {
+                let latest = folded.entry(fold_case(name)).or_insert(i);
+                *latest = i.max(*latest);
+            }
+            folded
+        });
+        folded.get(&fold_case(name)).map(|&i| &self.headers[i])
+    }
+
+    /// Indices of entries with names differing only by case, they overwrite each other
+    /// when extracted to case insensitive file system. Latest revision of each name is used.
+    pub fn case_conflicts(&self) -> Vec<Vec<usize>> {
+        let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+        for (name, &i) in &self.index {
+            groups.entry(fold_case(name)).or_default().push(i);
+        }
+        let mut conflicts = groups
+            .into_values()
+            .filter(|g| g.len() > 1)
+            .map(|mut g| {
+                g.sort_unstable();
+                g
+            })
+            .collect::<Vec<Vec<usize>>>();
+        conflicts.sort();
+        conflicts
+    }
+
+    /// Earliest and latest entries modification time, entries without mtime (zero) are skipped.
+    pub fn mtime_range(&self) -> Option<(SystemTime, SystemTime)> {
+        let mtimes = self.headers.iter().map(|h| h.mtime).filter(|&t| t > 0);
+        let (min, max) = mtimes.fold(None, |range, t| match range {
+            None => Some((t, t)),
+            Some((min, max)) => Some((t.min(min), t.max(max))),
+        })?;
+        let time = |t: u128| UNIX_EPOCH + Duration::from_secs(t as u64);
+        Some((time(min), time(max)))
+    }
+
+    /// All headers sorted by key, headers with equal keys keep source order.
+    pub fn sorted_by(&self, key: SortKey) -> Vec<&Header> {
+        let mut headers = self.headers.iter().collect::<Vec<&Header>>();
+        let (order, cmp): (SortOrder, fn(&&Header, &&Header) -> std::cmp::Ordering) = match key {
+            SortKey::Name(order) => (order, |a, b| a.name.cmp(&b.name)),
+            SortKey::Size(order) => (order, |a, b| a.size.cmp(&b.size)),
+            SortKey::Mtime(order) => (order, |a, b| a.mtime.cmp(&b.mtime)),
+        };
+        match order {
+            SortOrder::Asc => headers.sort_by(cmp),
+            SortOrder::Desc => headers.sort_by(|a, b| cmp(b, a)),
+        }
+        headers
+    }
+
+    /// Path, data offset in source and data size of every entry in source order,
+    /// enough to serve entry data by range requests without parsing the archive again.
+    pub fn index_entries(&self) -> Vec<(String, usize, usize)> {
+        self.headers
+            .iter()
+            .map(|h| (h.name.clone(), h.offset + BLOCK_SIZE, h.size))
+            .collect()
+    }
 
-        // println!("Checksums s:{:#o} u:{:#o}", signed_sum, unsigned_sum);
+    /// Headers grouped by the first path segment of name (`./` prefix is ignored), source order is kept.
+    /// Entries at the root get an empty key, directory entry itself (`dir/`) goes to its own group.
+    pub fn group_by_toplevel(&self) -> HashMap<String, Vec<&Header>> {
+        let mut groups: HashMap<String, Vec<&Header>> = HashMap::new();
+        for h in &self.headers {
+            let mut name = h.name.trim_start_matches('/');
+            while let Some(rest) = name.strip_prefix("./") {
+                name = rest;
+            }
+            let top = name.split_once('/').map_or("", |(top, _)| top);
+            groups.entry(top.to_string()).or_default().push(h);
+        }
+        groups
+    }
 
-        let checksum_raw = self.extract_string(HeaderProperty::Chksum);
-        let checksum = parse_isize(&checksum_raw).unwrap();
+    /// True if names go in ascending order (repeated names are fine), so binary search by name works.
+    pub fn is_sorted(&self) -> bool {
+        self.headers.windows(2).all(|w| w[0].name <= w[1].name)
+    }
 
-        if checksum < 0 {
-            return HeaderCheck::Invalid { not_ustar: false };
+    /// Header indices ordered by name, source order is kept for equal names.
+    pub fn sort_index(&self) -> Vec<usize> {
+        let mut indices = (0..self.headers.len()).collect::<Vec<usize>>();
+        indices.sort_by(|&a, &b| self.headers[a].name.cmp(&self.headers[b].name));
+        indices
+    }
+
+    /// Indices of entries hard linked together: the target entry followed by all `Link` entries to it.
+    /// Links to links are grouped by the final target, links to missing entries and circular ones
+    /// are not grouped.
+    pub fn hardlink_groups(&self) -> Vec<Vec<usize>> {
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        for (i, h) in self.headers.iter().enumerate() {
+            if h.typeflag != HeaderType::Link {
+                continue;
+            }
+            let target = match self.final_target(i, true) {
+                Ok(Some(t)) => t,
+                _ => continue,
+            };
+            match groups.iter_mut().find(|g| g[0] == target) {
+                Some(group) => group.push(i),
+                None => groups.push(vec![target, i]),
+            }
         }
+        groups
+    }
+
+    /// True if all entries have valid header (checksum and magic).
+    pub fn all_valid(&self) -> bool {
+        self.headers.iter().all(|h| h.check == HeaderCheck::Valid)
+    }
+
+    /// Entries with invalid header, only `from_reader` and `from_path` keep them.
+    pub fn invalid_entries(&self) -> Vec<&Header> {
+        self.headers.iter().filter(|h| h.check != HeaderCheck::Valid).collect()
+    }
 
-        if unsigned_sum != checksum as usize && signed_sum != checksum {
-            HeaderCheck::Invalid { not_ustar: false }
+    /// Entry that follows given one in source order.
+    pub fn next_entry(&self, header: &Header) -> Option<&Header> {
+        self.headers.get(self.position(header)? + 1)
+    }
+
+    /// Entry that precedes given one in source order.
+    pub fn prev_entry(&self, header: &Header) -> Option<&Header> {
+        self.headers.get(self.position(header)?.checked_sub(1)?)
+    }
+
+    /// Entry whose header or data (including padding) contains byte at offset in source.
+    pub fn entry_at_offset(&self, offset: usize) -> Option<&Header> {
+        let i = match self.headers.binary_search_by_key(&offset, |h| h.offset) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let h = &self.headers[i];
+        if offset < h.offset + BLOCK_SIZE + offset_by_blocks(h.size) {
+            Some(h)
         } else {
-            let magic = self.extract(HeaderProperty::Magic);
-            // alternatively could check for first 5 characters
-            // if magic[0..5] == HEADER_MAGIC[0..5] {
-            if magic == HEADER_MAGIC {
-                HeaderCheck::Valid
-            } else {
-                HeaderCheck::Invalid { not_ustar: true }
+            None
+        }
+    }
+
+    /// Index of header in source order, headers are matched by offset.
+    fn position(&self, header: &Header) -> Option<usize> {
+        self.headers.iter().position(|h| h.offset == header.offset)
+    }
+
+    /// Bytes wasted for padding: entries data padding plus standard two blocks terminator.
+    /// Extra zero blocks after terminator (padding to the blocking factor) are not known here.
+    pub fn total_padding(&self) -> u64 {
+        let entries: u64 = self.headers.iter().map(|h| h.padding() as u64).sum();
+        entries + (BLOCK_SIZE * 2) as u64
+    }
+
+    /// Textual listing with name, size, mode, mtime and typeflag per entry.
+    pub fn to_manifest(&self, format: ManifestFormat) -> String {
+        match format {
+            ManifestFormat::Csv => {
+                let mut out = String::from("name,size,mode,mtime,typeflag\n");
+                for h in &self.headers {
+                    out.push_str(&format!(
+                        "{},{},{:o},{},{:?}\n",
+                        csv_escape(&h.name),
+                        h.size,
+                        h.mode,
+                        h.mtime,
+                        h.typeflag
+                    ));
+                }
+                out
+            }
+            #[cfg(feature = "serde")]
+            ManifestFormat::Json => {
+                let rows = self
+                    .headers
+                    .iter()
+                    .map(|h| {
+                        serde_json::json!({
+                            "name": h.name,
+                            "size": h.size,
+                            "mode": format!("{:o}", h.mode),
+                            "mtime": h.mtime as u64,
+                            "typeflag": format!("{:?}", h.typeflag),
+                        })
+                    })
+                    .collect();
+                serde_json::Value::Array(rows).to_string()
+            }
+        }
+    }
+
+    /// Entry that link points to.
+    /// Hard links are resolved by archive name, symbolic links relative to the link location.
+    pub fn resolve_link(&self, header: &Header) -> Option<&Header> {
+        self.link_target(header).map(|i| &self.headers[i])
+    }
+
+    fn link_target(&self, header: &Header) -> Option<usize> {
+        let target = match header.typeflag {
+            HeaderType::Link => header.linkname.clone(),
+            HeaderType::Sym => resolve_symlink(&header.name, &header.linkname)?,
+            _ => return None,
+        };
+        self.lookup(&target)
+    }
+
+    /// Entry at the end of link chain (links to links are followed), None for dangling links
+    /// and entries that are not links. Chain that loops gives `TarError::CircularLink`.
+    pub fn resolve_link_final(&self, header: &Header) -> Result<Option<&Header>, TarError> {
+        let start = match self.headers.iter().position(|h| h.offset == header.offset) {
+            Some(i) => i,
+            None => return Ok(self.resolve_link(header)),
+        };
+        Ok(self.final_target(start, false)?.map(|i| &self.headers[i]))
+    }
+
+    /// Follow links (hard ones only or symbolic too) from entry with index `start`.
+    fn final_target(&self, start: usize, hard_only: bool) -> Result<Option<usize>, TarError> {
+        let is_link = |t: HeaderType| t == HeaderType::Link || (!hard_only && t == HeaderType::Sym);
+        if !is_link(self.headers[start].typeflag) {
+            return Ok(None);
+        }
+        let mut visited = HashSet::new();
+        let mut current = start;
+        while is_link(self.headers[current].typeflag) {
+            if !visited.insert(current) {
+                return Err(TarError::CircularLink {
+                    name: self.headers[start].name.clone(),
+                });
             }
+            current = match self.link_target(&self.headers[current]) {
+                Some(t) => t,
+                None => return Ok(None),
+            };
         }
+        Ok(Some(current))
     }
+
+    /// Names that appear more than once with indices of all their headers.
+    pub fn duplicates(&self) -> Vec<(String, Vec<usize>)> {
+        let mut found = Vec::new();
+        for (name, &last) in &self.index {
+            let mut indices = vec![last];
+            while let Some(prev) = self.headers[indices[indices.len() - 1]].prev {
+                indices.push(prev);
+            }
+            indices.reverse();
+            if indices.len() > 1 {
+                found.push((name.clone(), indices));
+            }
+        }
+        found.sort_by_key(|(_, indices)| indices[0]);
+        found
+    }
+
+    /// Hard links with target missing in archive.
+    /// Hard links must always point to a previously archived entry, so these are broken for sure.
+    pub fn dangling_hardlinks(&self) -> Vec<&Header> {
+        self.headers
+            .iter()
+            .filter(|h| h.typeflag == HeaderType::Link && !self.contains(&h.linkname))
+            .collect()
+    }
+
+    /// Symbolic links with target missing in archive.
+    /// Symbolic links could point outside of archive (absolute or escaping with ".."),
+    /// such links are also returned since they can't be resolved within archive.
+    pub fn dangling_symlinks(&self) -> Vec<&Header> {
+        self.headers
+            .iter()
+            .filter(|h| h.typeflag == HeaderType::Sym)
+            .filter(|h| match resolve_symlink(&h.name, &h.linkname) {
+                Some(target) => !self.contains(&target),
+                None => true,
+            })
+            .collect()
+    }
+}
+
+/// Quote CSV value if it contains special characters.
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Resolve symbolic link target relative to link location in archive.
+/// Returns None if target points outside of archive.
+fn resolve_symlink(link_name: &str, target: &str) -> Option<String> {
+    if target.starts_with('/') {
+        return None;
+    }
+    let mut parts = link_name
+        .split('/')
+        .filter(|p| !p.is_empty() && *p != ".")
+        .collect::<Vec<&str>>();
+    // drop link own name
+    parts.pop();
+    for p in target.split('/') {
+        match p {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            _ => parts.push(p),
+        }
+    }
+    Some(parts.join("/"))
 }
 
+#[cfg(test)]
+mod tests {
+    use hamcrest2::prelude::*;
+
+    use super::*;
+    use super::super::testutil::*;
 
-impl TarMeta {}
\ No newline at end of file
+    fn names(headers: Vec<&Header>) -> Vec<&str> {
+        headers.iter().map(|h| h.name.as_str()).collect()
+    }
+
+    fn files_test_meta() -> TarMeta {
+        TarMeta::from_path(test_resources_path().join("files_test.tar")).unwrap()
+    }
+
+    #[test]
+    fn circular_hardlinks() {
+        let mut source = archive(&[
+            entry(link("a.txt", b'1', "b.txt"), b""),
+            entry(link("b.txt", b'1', "a.txt"), b""),
+            file("data.txt", b"data"),
+            entry(link("copy.txt", b'1', "data.txt"), b""),
+            entry(link("copy_of_copy.txt", b'1', "copy.txt"), b""),
+        ]);
+        let meta = TarMeta::from_reader(&mut source).unwrap();
+        let headers = meta.iter().collect::<Vec<&Header>>();
+
+        match meta.resolve_link_final(headers[0]) {
+            Err(TarError::CircularLink { name }) => assert_that!(name.as_str(), equal_to("a.txt")),
+            other => panic!("unexpected {:?}", other),
+        }
+        let target = meta.resolve_link_final(headers[4]).unwrap().unwrap();
+        assert_that!(target.name.as_str(), equal_to("data.txt"));
+        assert_that!(meta.hardlink_groups(), equal_to(vec![vec![2, 3, 4]]));
+    }
+
+    #[test]
+    fn hardlinks_grouped() {
+        let mut source = archive(&[
+            file("data.txt", b"data"),
+            file("other.txt", b"other"),
+            entry(link("copy_1.txt", b'1', "data.txt"), b""),
+            entry(link("copy_2.txt", b'1', "data.txt"), b""),
+            entry(link("lost.txt", b'1', "missing.txt"), b""),
+        ]);
+        let meta = TarMeta::from_reader(&mut source).unwrap();
+
+        assert_that!(meta.hardlink_groups(), equal_to(vec![vec![0, 2, 3]]));
+    }
+
+    #[test]
+    fn case_insensitive_names() {
+        let mut source = archive(&[
+            file("Foo.txt", b"upper"),
+            file("bar.txt", b"bar"),
+            file("foo.txt", b"lower"),
+        ]);
+        let meta = TarMeta::from_reader(&mut source).unwrap();
+
+        assert_that!(meta.get_ci("Foo.txt").unwrap().size, equal_to(5));
+        assert_that!(meta.get_ci("BAR.TXT").unwrap().name.as_str(), equal_to("bar.txt"));
+        assert_that!(meta.get_ci("FOO.txt").unwrap().name.as_str(), equal_to("foo.txt"));
+        assert!(meta.get_ci("baz.txt").is_none());
+        assert_that!(meta.case_conflicts(), equal_to(vec![vec![0, 2]]));
+    }
+
+    #[test]
+    fn invalid_entries_listed() {
+        let mut data = std::fs::read(test_resources_path().join("files_test.tar")).unwrap();
+        // Broken checksum of the second header
+        data[BLOCK_SIZE * 2] = b'F';
+        let meta = TarMeta::from_reader(&mut std::io::Cursor::new(data)).unwrap();
+
+        assert_that!(meta.len(), equal_to(4));
+        assert!(!meta.all_valid());
+        let invalid = meta.invalid_entries();
+        assert_that!(names(invalid.clone()), equal_to(vec!["File_2.txt"]));
+        assert_that!(invalid[0].check, equal_to(HeaderCheck::Invalid { not_ustar: false }));
+        assert!(files_test_meta().all_valid());
+    }
+
+    #[test]
+    fn navigate_entries() {
+        let meta = files_test_meta();
+        let first = meta.iter().next().unwrap();
+
+        let second = meta.next_entry(first).unwrap();
+        assert_that!(second.name.as_str(), equal_to("file_2.txt"));
+        assert_that!(meta.prev_entry(second).map(|h| h.name.as_str()), equal_to(Some("file_1.txt")));
+        assert!(meta.prev_entry(first).is_none());
+        assert!(meta.next_entry(meta.get("file_4.txt").unwrap()).is_none());
+    }
+
+    #[test]
+    fn entry_by_offset() {
+        let meta = files_test_meta();
+        let third = meta.get("file_3_dir/file_3.txt").unwrap();
+        let in_data = third.header_offset() + BLOCK_SIZE + 520;
+
+        assert_that!(meta.entry_at_offset(in_data).map(|h| h.offset), equal_to(Some(third.offset)));
+        assert_that!(meta.entry_at_offset(0).map(|h| h.name.as_str()), equal_to(Some("file_1.txt")));
+        assert!(meta.entry_at_offset(BLOCK_SIZE * 9).is_none());
+    }
+
+    #[test]
+    fn meta_from_path() {
+        let meta = files_test_meta();
+        assert_that!(meta.len(), equal_to(4));
+        assert_that!(meta.get("file_2.txt").map(|h| h.size), equal_to(Some(15)));
+        assert!(TarMeta::from_path(test_resources_path().join("missing.tar")).is_err());
+    }
+
+    #[test]
+    fn manifest_csv() {
+        let manifest = files_test_meta().to_manifest(ManifestFormat::Csv);
+        let rows = manifest.lines().collect::<Vec<&str>>();
+
+        assert_that!(rows.len(), equal_to(5));
+        assert_that!(rows[0], equal_to("name,size,mode,mtime,typeflag"));
+        assert_that!(rows[1], equal_to("file_1.txt,512,664,1580641217,Reg"));
+        assert_that!(csv_escape("a,\"b\""), equal_to("\"a,\"\"b\"\"\"".to_string()));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn manifest_json() {
+        let manifest = files_test_meta().to_manifest(ManifestFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&manifest).unwrap();
+
+        assert_that!(value.as_array().unwrap().len(), equal_to(4));
+        assert_that!(value[2]["name"].as_str(), equal_to(Some("file_3_dir/file_3.txt")));
+        assert_that!(value[2]["size"].as_u64(), equal_to(Some(521)));
+    }
+
+    #[test]
+    fn peek_binary_data() {
+        let png = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0];
+        let mut source = archive(&[file("image.png", &png), file("short.bin", b"ab")]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        source.set_position(7);
+
+        assert_that!(headers[0].peek_data(&mut source, 4).unwrap(), equal_to(png[..4].to_vec()));
+        assert_that!(headers[1].peek_data(&mut source, 4).unwrap(), equal_to(b"ab".to_vec()));
+        assert_that!(source.position(), equal_to(7));
+    }
+
+    #[test]
+    fn extractable_entries() {
+        let mut source = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut source).emit_metadata(true).collect::<Vec<Header>>();
+        let extractable = headers.iter().map(|h| h.is_extractable()).collect::<Vec<bool>>();
+
+        assert_that!(extractable, equal_to(vec![false, true, false, true]));
+        assert!(!HeaderType::GnuVolumeLabel.is_extractable());
+        assert!(!HeaderType::Dumpdir.is_extractable());
+    }
+
+    #[test]
+    fn negative_mtime() {
+        // 1969-12-31 00:00:00
+        let mut field = [0xff; 12];
+        field[8..].copy_from_slice(&(-86400_i32).to_be_bytes());
+        let mut ph = header("old.txt", b'0', 0);
+        ph.set_field(HeaderProperty::Mtime, &field);
+        let h = Header::from(ph);
+
+        assert_that!(h.mtime, equal_to(0));
+        assert_that!(h.mtime_signed(), equal_to(-86400));
+        assert_that!(h.modified(), equal_to(UNIX_EPOCH.checked_sub(Duration::from_secs(86400))));
+
+        let h = Header::from(header("new.txt", b'0', 0));
+        assert_that!(h.modified(), equal_to(Some(UNIX_EPOCH + Duration::from_secs(0o13615525701))));
+    }
+
+    #[test]
+    fn header_field_ranges() {
+        let ranges = HeaderProperty::field_ranges();
+        let chksum = ranges.iter().find(|(name, _)| *name == "chksum").map(|(_, r)| r.clone());
+
+        assert_that!(chksum, equal_to(Some(148..156)));
+        assert!(ranges.windows(2).all(|w| w[0].1.end == w[1].1.start));
+        assert_that!(ranges.last().unwrap().1.end, equal_to(HEADER_SIZE));
+    }
+
+    #[test]
+    fn checked_numeric_fields() {
+        let with_size = |size: &[u8]| {
+            let mut ph = header("sized.txt", b'0', 0);
+            ph.set_field(HeaderProperty::Size, size);
+            Header::from(ph)
+        };
+        assert_that!(with_size(b"00000000017").size_checked(), equal_to(Ok(15)));
+        assert_that!(with_size(b"0000000001x").size_checked(), equal_to(Err(FieldError::Malformed)));
+        assert_that!(with_size(b"").size_checked(), equal_to(Err(FieldError::Malformed)));
+
+        let mut huge = [0xff; 12];
+        huge[0] = 0x80;
+        let h = with_size(&huge);
+        assert_that!(h.size_checked(), equal_to(Err(FieldError::Overflow)));
+        assert_that!(h.mtime_checked(), equal_to(Ok(0o13615525701)));
+    }
+
+    #[test]
+    fn raw_magic_and_version() {
+        let mut source = archive(&[file("posix.txt", b"data")]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(&headers[0].magic(), equal_to(b"ustar\0"));
+        assert_that!(&headers[0].version(), equal_to(b"00"));
+
+        let gnu = &files_test_meta().iter().next().unwrap().magic();
+        assert_that!(gnu, equal_to(HEADER_MAGIC_GNU));
+    }
+
+    #[test]
+    fn xattrs_from_pax() {
+        let mut source = archive(&[
+            pax(&[("SCHILY.xattr.user.comment", "hello"), ("LIBARCHIVE.xattr.user.note", "d29ybGQ=")]),
+            file("attrs.txt", b"data"),
+        ]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        let xattrs = headers[0].xattrs();
+
+        assert_that!(xattrs.len(), equal_to(2));
+        assert_that!(xattrs["user.comment"].as_slice(), equal_to(&b"hello"[..]));
+        assert_that!(xattrs["user.note"].as_slice(), equal_to(&b"world"[..]));
+    }
+
+    #[test]
+    fn mtime_range_skips_zero() {
+        let with_mtime = |name: &str, mtime: &[u8]| {
+            let mut ph = header(name, b'0', 0);
+            ph.set_field(HeaderProperty::Mtime, mtime);
+            ph.repair_checksum();
+            entry(ph, b"")
+        };
+        let mut source = archive(&[
+            with_mtime("old.txt", b"00000001750"),
+            file("new.txt", b""),
+            with_mtime("unset.txt", b"00000000000"),
+        ]);
+        let meta = TarMeta::from_reader(&mut source).unwrap();
+
+        let (min, max) = meta.mtime_range().unwrap();
+        assert_that!(min, equal_to(UNIX_EPOCH + Duration::from_secs(1000)));
+        assert_that!(max, equal_to(UNIX_EPOCH + Duration::from_secs(0o13615525701)));
+        assert_that!(TarMeta::from(Vec::new()).mtime_range(), equal_to(None));
+    }
+
+    #[test]
+    fn sorted_by_size_and_name() {
+        let meta = files_test_meta();
+        let by_size = meta.sorted_by(SortKey::Size(SortOrder::Desc));
+        assert_that!(by_size[0].name.as_str(), equal_to("file_3_dir/file_3.txt"));
+        assert_that!(by_size[0].size, equal_to(521));
+
+        let by_name = meta.sorted_by(SortKey::Name(SortOrder::Asc));
+        assert_that!(names(by_name), equal_to(vec!["file_1.txt", "file_2.txt", "file_3_dir/file_3.txt", "file_4.txt"]));
+    }
+
+    #[test]
+    fn duplicate_names() {
+        let mut source = archive(&[file("a.txt", b"1"), file("b.txt", b"2"), file("a.txt", b"3"), file("a.txt", b"4")]);
+        let meta = TarMeta::from(HeadersParser::from(&mut source));
+
+        assert_that!(meta.duplicates(), equal_to(vec![("a.txt".to_string(), vec![0, 2, 3])]));
+        assert!(files_test_meta().duplicates().is_empty());
+    }
+
+    #[test]
+    fn lenient_version() {
+        for version in [b"00", b" \0", b"  "] {
+            let mut ph = header("file.txt", b'0', 0);
+            ph.set_field(HeaderProperty::Version, version);
+            ph.repair_checksum();
+            assert_that!(ph.check(), equal_to(HeaderCheck::Valid));
+            assert_that!(ph.version(), equal_to(&version[..]));
+            assert_that!(ph.version_known(), is(true));
+            assert_that!(ph.structure_issues().is_empty(), is(true));
+        }
+
+        let mut ph = header("file.txt", b'0', 0);
+        ph.set_field(HeaderProperty::Version, b"01");
+        ph.repair_checksum();
+        assert_that!(ph.check(), equal_to(HeaderCheck::Valid));
+        assert_that!(ph.version_known(), is(false));
+        assert_that!(ph.structure_issues(), equal_to(vec![HeaderIssue::UnknownVersion]));
+    }
+
+    #[test]
+    fn reverse_iteration() {
+        let meta = TarMeta::from_path(test_resources_path().join("files_append_test.tar")).unwrap();
+        let offsets = meta.iter_rev().map(|h| h.offset).collect::<Vec<usize>>();
+        assert_that!(offsets.len(), equal_to(3));
+        assert!(offsets.windows(2).all(|w| w[0] > w[1]));
+
+        let newest = meta.iter_rev().next().unwrap();
+        assert_that!(newest.offset, equal_to(meta.get(&newest.name).unwrap().offset));
+        assert_that!(newest.prev, equal_to(Some(1)));
+    }
+
+    #[test]
+    fn data_crc32() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+        // zlib.crc32 of test/file_2.txt
+        assert_that!(headers[1].crc32(&mut file).unwrap(), equal_to(0x214e_c2d5));
+    }
+
+    #[test]
+    fn text_field_issues() {
+        let mut ph = header("file.txt", b'0', 0);
+        ph.set_field(HeaderProperty::Uname, b"ru\x07st");
+        ph.set_field(HeaderProperty::Gname, &[b'g'; 32]);
+        ph.repair_checksum();
+
+        assert_that!(
+            ph.structure_issues(),
+            equal_to(vec![
+                HeaderIssue::Unprintable(TextField::Uname),
+                HeaderIssue::Unterminated(TextField::Gname),
+            ])
+        );
+
+        let ph = header(&"n".repeat(100), b'0', 0);
+        assert_that!(ph.structure_issues(), equal_to(vec![HeaderIssue::Unterminated(TextField::Name)]));
+
+        // Full width name is fine when path continues in prefix
+        let mut ph = header(&"n".repeat(100), b'0', 0);
+        ph.set_field(HeaderProperty::Prefix, b"dir");
+        ph.repair_checksum();
+        assert!(ph.structure_issues().is_empty());
+    }
+
+    #[test]
+    fn contains_normalized_names() {
+        let meta = TarMeta::from_reader(&mut archive(&[
+            entry(header("./dir/", b'5', 0), b""),
+            file("./dir/file.txt", b"data"),
+            file("plain.txt", b"data"),
+        ]))
+        .unwrap();
+
+        assert!(meta.contains("./dir/file.txt"));
+        assert!(meta.contains("dir/file.txt"));
+        assert!(meta.contains("dir"));
+        assert!(meta.contains("./plain.txt"));
+        assert!(!meta.contains("missing.txt"));
+        assert!(!meta.contains("dir/file"));
+        assert_that!(meta.get("dir/").map(|h| h.offset), equal_to(Some(0)));
+    }
+
+    #[test]
+    fn flat_entries_index() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let index = TarMeta::from_reader(&mut file).unwrap().index_entries();
+        assert_that!(index.len(), equal_to(4));
+        assert_that!(index[2].clone(), equal_to(("file_3_dir/file_3.txt".to_string(), 2048 + BLOCK_SIZE, 521)));
+
+        let (_, offset, size) = &index[1];
+        let mut data = vec![0; *size];
+        file.seek(SeekFrom::Start(*offset as u64)).unwrap();
+        file.read_exact(&mut data).unwrap();
+        assert_that!(data, equal_to(std::fs::read(test_resources_path().join("file_2.txt")).unwrap()));
+    }
+
+    #[test]
+    fn grouped_by_toplevel() {
+        let meta = TarMeta::from_reader(&mut archive(&[
+            entry(header("docs/", b'5', 0), b""),
+            file("docs/a.txt", b"a"),
+            file("./src/lib.rs", b"lib"),
+            file("README", b"readme"),
+            file("src/sub/mod.rs", b"mod"),
+        ]))
+        .unwrap();
+
+        let groups = meta.group_by_toplevel();
+        let names = |key: &str| groups[key].iter().map(|h| h.name.as_str()).collect::<Vec<&str>>();
+        assert_that!(groups.len(), equal_to(3));
+        assert_that!(names("docs"), equal_to(vec!["docs/", "docs/a.txt"]));
+        assert_that!(names("src"), equal_to(vec!["./src/lib.rs", "src/sub/mod.rs"]));
+        assert_that!(names(""), equal_to(vec!["README"]));
+    }
+
+    #[test]
+    fn prefix_field() {
+        let mut ph = header("file.txt", b'0', 0);
+        ph.set_field(HeaderProperty::Prefix, b"some/long/dir");
+        ph.repair_checksum();
+        let h = Header::from(ph);
+        assert_that!(h.prefix.as_str(), equal_to("some/long/dir"));
+        assert_that!(h.name.as_str(), equal_to("some/long/dir/file.txt"));
+
+        // GNU header area is not a prefix
+        let gnu = files_test_meta().iter().next().unwrap().clone();
+        assert!(gnu.prefix.is_empty());
+    }
+
+    #[test]
+    fn sorted_name_index() {
+        let meta = TarMeta::from_reader(&mut archive(&[file("b.txt", b"b"), file("a.txt", b"a"), file("c.txt", b"c")]))
+            .unwrap();
+        assert!(!meta.is_sorted());
+        let index = meta.sort_index();
+        assert_that!(index.clone(), equal_to(vec![1, 0, 2]));
+        let names = index.iter().map(|&i| meta.headers[i].name.as_str()).collect::<Vec<&str>>();
+        assert_that!(names, equal_to(vec!["a.txt", "b.txt", "c.txt"]));
+
+        assert!(files_test_meta().is_sorted());
+    }
+
+    #[test]
+    #[cfg(feature = "bytemuck")]
+    fn raw_header_layout() {
+        use std::mem::{offset_of, size_of};
+
+        let fields = [
+            (offset_of!(RawUstarHeader, name), HeaderProperty::Name),
+            (offset_of!(RawUstarHeader, mode), HeaderProperty::Mode),
+            (offset_of!(RawUstarHeader, uid), HeaderProperty::Uid),
+            (offset_of!(RawUstarHeader, gid), HeaderProperty::Gid),
+            (offset_of!(RawUstarHeader, size), HeaderProperty::Size),
+            (offset_of!(RawUstarHeader, mtime), HeaderProperty::Mtime),
+            (offset_of!(RawUstarHeader, chksum), HeaderProperty::Chksum),
+            (offset_of!(RawUstarHeader, typeflag), HeaderProperty::Typeflag),
+            (offset_of!(RawUstarHeader, linkname), HeaderProperty::Linkname),
+            (offset_of!(RawUstarHeader, magic), HeaderProperty::Magic),
+            (offset_of!(RawUstarHeader, version), HeaderProperty::Version),
+            (offset_of!(RawUstarHeader, uname), HeaderProperty::Uname),
+            (offset_of!(RawUstarHeader, gname), HeaderProperty::Gname),
+            (offset_of!(RawUstarHeader, devmajor), HeaderProperty::Devmajor),
+            (offset_of!(RawUstarHeader, devminor), HeaderProperty::Devminor),
+            (offset_of!(RawUstarHeader, prefix), HeaderProperty::Prefix),
+        ];
+        for (offset, range) in fields {
+            assert_that!(offset, equal_to(range.start));
+        }
+        assert_that!(size_of::<RawUstarHeader>(), equal_to(BLOCK_SIZE));
+
+        let ph = header("raw.txt", b'0', 5);
+        let raw = ph.as_raw();
+        assert_that!(&raw.name[..7], equal_to(&b"raw.txt"[..]));
+        assert_that!(&raw.magic, equal_to(b"ustar\0"));
+        assert_that!(raw.typeflag, equal_to(b'0'));
+    }
+
+    #[test]
+    fn hexdump_text_entry() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+        let dump = headers[1].hexdump(&mut file, 64).unwrap();
+
+        assert_that!(
+            dump.lines().next(),
+            equal_to(Some("00000000: 6669 6c65 2032 2063 6f6e 7465 6e74 0a    file 2 content."))
+        );
+        assert_that!(headers[0].hexdump(&mut file, 40).unwrap().lines().count(), equal_to(3));
+    }
+
+    #[test]
+    fn total_padding_for_archive() {
+        let meta = files_test_meta();
+        let paddings = meta.iter().map(|h| h.padding()).collect::<Vec<usize>>();
+
+        // sizes are 512, 15, 521, 16
+        assert_that!(paddings, equal_to(vec![0, 497, 503, 496]));
+        assert_that!(meta.total_padding(), equal_to(497 + 503 + 496 + 1024));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let meta = files_test_meta();
+        let json = serde_json::to_string(&meta).unwrap();
+        let restored: TarMeta = serde_json::from_str(&json).unwrap();
+
+        assert_that!(restored.len(), equal_to(meta.len()));
+        for (a, b) in meta.iter().zip(restored.iter()) {
+            assert_that!(&b.name, equal_to(&a.name));
+            assert_that!(b.header_offset(), equal_to(a.header_offset()));
+            assert_that!(b.typeflag, equal_to(a.typeflag));
+            assert_that!(b.check, equal_to(a.check));
+            assert_that!(b.modified(), equal_to(a.modified()));
+            assert_that!(b.magic(), equal_to(a.magic()));
+            assert_that!(b.size_checked(), equal_to(a.size_checked()));
+        }
+        assert!(restored.iter().all(|h| h.modified() != Some(UNIX_EPOCH)));
+        assert_that!(restored.get("file_4.txt").map(|h| h.size), equal_to(Some(16)));
+    }
+
+    #[test]
+    fn dangling_links() {
+        let mut source = archive(&[
+            file("dir/file.txt", b"data"),
+            entry(link("dir/hard_ok", b'1', "dir/file.txt"), b""),
+            entry(link("dir/hard_broken", b'1', "dir/missing.txt"), b""),
+            entry(link("dir/sym_ok", b'2', "file.txt"), b""),
+            entry(link("dir/sym_up", b'2', "../dir/file.txt"), b""),
+            entry(link("dir/sym_external", b'2', "/etc/passwd"), b""),
+        ]);
+        let meta = TarMeta::from(HeadersParser::from(&mut source));
+
+        assert_that!(names(meta.dangling_hardlinks()), equal_to(vec!["dir/hard_broken"]));
+        assert_that!(names(meta.dangling_symlinks()), equal_to(vec!["dir/sym_external"]));
+    }
+}
\ No newline at end of file
@@ -1,69 +1,250 @@
 use core::clone::Clone;
 use core::cmp::PartialEq;
-use core::iter::Iterator;
 use core::num::ParseIntError;
-use core::ops::Range;
-use std::io::{Read, Seek, SeekFrom};
-
-use self::meta::Header;
-use self::meta::HeaderCheck;
-use self::meta::HeaderType;
-use self::meta::PosixHeader;
 
+#[cfg(any(feature = "sha1", feature = "sha2"))]
+pub mod digest;
+pub mod extract;
+#[cfg(feature = "http")]
+pub mod http;
 pub mod meta;
+pub mod pax;
 pub mod read;
+pub mod stream;
 pub mod write;
 
+#[cfg(test)]
+mod testutil;
+
+use meta::{FieldError, NumericField};
+
 pub const BLOCK_SIZE: usize = 512;
 
 
+/// Moving over entry data that is not needed.
+pub trait SkipData {
+    /// Skip `n` bytes of source.
+    fn skip_data(&mut self, n: u64) -> std::io::Result<()>;
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ErrorTar {
     InvalidBlockSize,
 }
 
 /// Giver bytes count return offset that divisible by blocks size.
+/// Counts close to `usize::MAX` (broken headers) saturate to the largest such offset.
 fn offset_by_blocks(bytes_count: usize) -> usize {
-    let offset = bytes_count / BLOCK_SIZE * BLOCK_SIZE;
-    if bytes_count % BLOCK_SIZE == 0 {
-        offset
-    } else {
-        offset + BLOCK_SIZE
-    }
+    bytes_count
+        .checked_next_multiple_of(BLOCK_SIZE)
+        .unwrap_or(usize::MAX - usize::MAX % BLOCK_SIZE)
 }
 
 /// Just read usize from string
 fn parse_usize(string: &str) -> Result<usize, ParseIntError> {
-    let strval = &string.trim_end_matches(char::from(0));
+    let strval = string.trim_end_matches(char::from(0));
     // println!("usize parsed from {}", strval);
     usize::from_str_radix(strval, 8)
 }
 
-/// Just read isize from string
-fn parse_isize(string: &str) -> Result<isize, ParseIntError> {
-    let strval = &string.trim_matches(char::from(0));
-    // println!("Isize parsed from {} {:?}", strval, strval.as_bytes());
-    isize::from_str_radix(strval, 8)
+/// Read octal number from raw bytes without allocations.
+/// Leading spaces and trailing spaces or zeroes are ignored.
+fn parse_octal(bytes: &[u8]) -> Option<usize> {
+    let mut value: usize = 0;
+    let mut digits = 0;
+    for &b in bytes.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => {
+                value = value.checked_mul(8)?.checked_add((b - b'0') as usize)?;
+                digits += 1;
+            }
+            0 | b' ' => break,
+            _ => return None,
+        }
+    }
+    if digits > 0 {
+        Some(value)
+    } else {
+        None
+    }
 }
 
-/// Return key from slice of pairs (K,V) by value.
-fn pair_match_value<K: Clone, V: PartialEq>(value: V, pairs: &[(K, V)]) -> Option<K> {
-    for i in 0..pairs.len() {
-        let p = &pairs[i];
-        if p.1 == value {
-            return Some(p.0.clone());
+/// Decode GNU base-256 (binary) numeric field.
+///
+/// Such fields are marked by the high bit of the first byte, the rest is big-endian
+/// two's complement number: `0x80` first byte means positive value, `0xff` negative one.
+/// Fields up to 16 bytes fit into result (header fields are 12 bytes at most).
+pub fn decode_base256(bytes: &[u8]) -> i128 {
+    let negative = bytes.first().is_some_and(|&b| b & 0x40 != 0);
+    let inverse = if negative { 0xff } else { 0 };
+    let mut value: i128 = 0;
+    for (i, &b) in bytes.iter().enumerate() {
+        let mut b = b ^ inverse;
+        if i == 0 {
+            // Drop marker bit
+            b &= 0x7f;
         }
+        value = (value << 8) | b as i128;
+    }
+    if negative {
+        !value
+    } else {
+        value
+    }
+}
+
+/// Strict version of `parse_numeric` for non negative fields.
+/// Octal digits could be surrounded by spaces and followed by NULs, anything else is malformed.
+fn parse_field(bytes: &[u8]) -> NumericField {
+    parse_field_with(bytes, false)
+}
+
+/// Best effort version of `parse_field`: the longest leading octal run is used,
+/// whatever follows it is ignored (e.g. `644 garbage` gives 0o644).
+fn parse_field_lenient(bytes: &[u8]) -> NumericField {
+    parse_field_with(bytes, true)
+}
+
+fn parse_field_with(bytes: &[u8], lenient: bool) -> NumericField {
+    match bytes.first() {
+        Some(&b) if b & 0x80 != 0 => {
+            if b & 0x40 != 0 {
+                return Err(FieldError::Malformed);
+            }
+            // Only 8 lowest bytes could be used by u64 (marker bit is not a part of value)
+            let (high, _) = bytes.split_at(bytes.len().saturating_sub(8));
+            let high_set = high.iter().enumerate().any(|(i, &b)| if i == 0 { b & 0x7f != 0 } else { b != 0 });
+            if high_set {
+                return Err(FieldError::Overflow);
+            }
+            Ok(decode_base256(bytes) as u64)
+        }
+        _ => {
+            let start = bytes.iter().take_while(|&&b| b == b' ').count();
+            let digits = bytes[start..].iter().take_while(|b| (b'0'..=b'7').contains(*b)).count();
+            let rest = &bytes[start + digits..];
+            if digits == 0 {
+                return Err(FieldError::Malformed);
+            }
+            if !lenient {
+                // Only spaces and NULs (in any order) may follow digits
+                if rest.iter().any(|&b| b != 0 && b != b' ') {
+                    return Err(FieldError::Malformed);
+                }
+            }
+            bytes[start..start + digits].iter().try_fold(0_u64, |v, &b| {
+                v.checked_mul(8)
+                    .and_then(|v| v.checked_add((b - b'0') as u64))
+                    .ok_or(FieldError::Overflow)
+            })
+        }
+    }
+}
+
+/// Strict decimal number (PAX record values).
+pub(crate) fn parse_decimal_field(bytes: &[u8]) -> NumericField {
+    if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_digit) {
+        return Err(FieldError::Malformed);
     }
-    None
+    bytes.iter().try_fold(0_u64, |v, &b| {
+        v.checked_mul(10)
+            .and_then(|v| v.checked_add((b - b'0') as u64))
+            .ok_or(FieldError::Overflow)
+    })
+}
+
+/// Read numeric header field in octal or base-256 form.
+fn parse_numeric(bytes: &[u8]) -> Option<i128> {
+    match bytes.first() {
+        Some(&b) if b & 0x80 != 0 => Some(decode_base256(bytes)),
+        _ => parse_octal(bytes).map(|v| v as i128),
+    }
+}
+
+/// CRC-32 (IEEE, reflected 0xEDB88320 polynomial) lookup table.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continue CRC-32 calculation, start with 0 and feed data by chunks.
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!crc, |crc, &b| {
+        CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Return key from slice of pairs (K,V) by value.
+fn pair_match_value<K: Clone, V: PartialEq>(value: V, pairs: &[(K, V)]) -> Option<K> {
+    pairs.iter().find(|p| p.1 == value).map(|p| p.0.clone())
 }
 
 /// Return value from slice of pairs (K,V) by key.
 fn pair_match_key<K: PartialEq, V: Clone>(key: K, pairs: &[(K, V)]) -> Option<V> {
-    for i in 0..pairs.len() {
-        let p = &pairs[i];
-        if p.0 == key {
-            return Some(p.1.clone());
-        }
+    pairs.iter().find(|p| p.0 == key).map(|p| p.1.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use hamcrest2::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn base256_positive() {
+        let mut field = [0; 12];
+        field[0] = 0x80;
+        field[8..].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_that!(decode_base256(&field), equal_to(0xffff_ffff));
+
+        // 8 GiB size that does not fit into 11 octal digits
+        let mut field = [0; 12];
+        field[0] = 0x80;
+        field[7] = 0x02;
+        assert_that!(decode_base256(&field), equal_to(8 * 1024 * 1024 * 1024));
+        assert_that!(parse_numeric(&field), equal_to(Some(8 * 1024 * 1024 * 1024)));
+    }
+
+    #[test]
+    fn base256_negative() {
+        assert_that!(decode_base256(&[0xff; 12]), equal_to(-1));
+
+        // One day before epoch
+        let mut field = [0xff; 12];
+        field[8..].copy_from_slice(&(-86400_i32).to_be_bytes());
+        assert_that!(decode_base256(&field), equal_to(-86400));
+        assert_that!(parse_numeric(&field), equal_to(Some(-86400)));
+        assert_that!(parse_numeric(b"00000000017\0"), equal_to(Some(15)));
+    }
+
+    #[test]
+    fn lenient_numeric_field() {
+        assert_that!(parse_field(b"644 garbage"), equal_to(Err(FieldError::Malformed)));
+        assert_that!(parse_field_lenient(b"644 garbage"), equal_to(Ok(0o644)));
+        assert_that!(parse_field_lenient(b" 0017x"), equal_to(Ok(0o17)));
+        assert_that!(parse_field_lenient(b"garbage"), equal_to(Err(FieldError::Malformed)));
+
+        assert_that!(parse_field(b" 0000644 \0"), equal_to(Ok(0o644)));
+        assert_that!(parse_field(b"0000644\0\0\0"), equal_to(Ok(0o644)));
+        assert_that!(parse_field(b"0000644\0 \0"), equal_to(Ok(0o644)));
+        assert_that!(parse_field(b"0000644\0x\0"), equal_to(Err(FieldError::Malformed)));
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_that!(crc32_update(0, b"123456789"), equal_to(0xcbf4_3926));
+        let chunked = crc32_update(crc32_update(0, b"1234"), b"56789");
+        assert_that!(chunked, equal_to(0xcbf4_3926));
     }
-    None
 }
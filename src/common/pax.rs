@@ -0,0 +1,176 @@
+// https://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html#tag_20_92_13_03
+// https://www.gnu.org/software/tar/manual/html_node/Standard.html
+use std::convert::TryFrom;
+
+use super::meta::{Header, HeaderType};
+use super::parse_decimal_field;
+
+/// PAX record as key and raw value (values are not always UTF-8).
+pub type PaxRecord = (String, Vec<u8>);
+
+/// Parse PAX extended header data that consists of "<length> <key>=<value>\n" records.
+/// Parsing stops on the first malformed record.
+pub fn parse_records(data: &[u8]) -> Vec<PaxRecord> {
+    let mut records = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = match rest.iter().position(|&b| b == b' ') {
+            Some(p) => p,
+            None => break,
+        };
+        let len = match std::str::from_utf8(&rest[..space]).ok().and_then(|s| s.parse::<usize>().ok()) {
+            Some(l) if l > space + 1 && l <= rest.len() => l,
+            _ => break,
+        };
+        let record = match rest[space + 1..len].split_last() {
+            Some((b'\n', r)) => r,
+            _ => break,
+        };
+        let eq = match record.iter().position(|&b| b == b'=') {
+            Some(p) => p,
+            None => break,
+        };
+        let key = String::from_utf8_lossy(&record[..eq]).into_owned();
+        records.push((key, record[eq + 1..].to_vec()));
+        rest = &rest[len..];
+    }
+    records
+}
+
+/// Format single PAX record, length prefix includes itself.
+pub fn format_record(key: &str, value: &[u8]) -> Vec<u8> {
+    // " " + key + "=" + value + "\n"
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len.to_string().len() + rest > len {
+        len += 1;
+    }
+    let mut record = format!("{} {}=", len, key).into_bytes();
+    record.extend_from_slice(value);
+    record.push(b'\n');
+    record
+}
+
+/// Read decimal PAX number, fractional part (used for times) is dropped.
+fn parse_decimal(value: &[u8]) -> Option<u128> {
+    let s = std::str::from_utf8(value).ok()?;
+    let integer = s.split('.').next()?;
+    integer.parse::<u128>().ok()
+}
+
+/// Same as `parse_decimal` but negative values (e.g. times before epoch) are allowed.
+pub(crate) fn parse_signed_decimal(value: &[u8]) -> Option<i128> {
+    let s = std::str::from_utf8(value).ok()?;
+    let integer = s.split('.').next()?;
+    integer.parse::<i128>().ok()
+}
+
+/// Decode standard base64 (padding is optional), None on invalid input.
+pub fn decode_base64(value: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(value.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for &b in value.iter().filter(|&&b| b != b'=') {
+        let v = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Override header properties from PAX records.
+pub fn apply_records(header: &mut Header, records: &[PaxRecord]) {
+    for (key, value) in records {
+        let text = || String::from_utf8_lossy(value).into_owned();
+        match key.as_str() {
+            "path" => header.name = text(),
+            "linkpath" => header.linkname = text(),
+            "uname" => header.uname = text(),
+            "gname" => header.gname = text(),
+            // Sizes that do not fit are ignored, header keeps its own one
+            "size" => {
+                if let Some(size) = parse_decimal_field(value).ok().and_then(|v| usize::try_from(v).ok()) {
+                    header.size = size;
+                }
+            }
+            "mtime" => {
+                if let Some(mtime) = parse_decimal(value) {
+                    header.mtime = mtime;
+                }
+            }
+            "uid" => header.uid = parse_decimal(value).map_or(header.uid, |v| v as u64),
+            "gid" => header.gid = parse_decimal(value).map_or(header.gid, |v| v as u64),
+            "atime" => header.atime = parse_decimal(value).or(header.atime),
+            "ctime" => header.ctime = parse_decimal(value).or(header.ctime),
+            _ => {}
+        }
+    }
+}
+
+/// Metadata from pseudo entries (PAX and GNU long names) waiting to be applied to the next real entry.
+#[derive(Debug, Default)]
+pub(crate) struct PendingMeta {
+    global: Vec<PaxRecord>,
+    local: Vec<PaxRecord>,
+    long_name: Option<String>,
+    long_link: Option<String>,
+}
+
+impl PendingMeta {
+    /// Remember data of metadata entry.
+    pub fn consume(&mut self, typeflag: HeaderType, data: &[u8]) {
+        match typeflag {
+            HeaderType::Xhd => self.local.extend(parse_records(data)),
+            HeaderType::Xlg => self.global.extend(parse_records(data)),
+            HeaderType::GnuLongName => self.long_name = Some(gnu_long_value(data)),
+            HeaderType::GnuLongLink => self.long_link = Some(gnu_long_value(data)),
+            _ => {}
+        }
+    }
+
+    /// True if there is nothing to apply to the next entry.
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty() && self.local.is_empty() && self.long_name.is_none() && self.long_link.is_none()
+    }
+
+    /// Apply collected metadata to the real entry header.
+    /// Everything except global PAX records is discarded afterwards.
+    pub fn apply(&mut self, header: &mut Header) {
+        if let Some(name) = self.long_name.take() {
+            header.name = name;
+        }
+        if let Some(link) = self.long_link.take() {
+            header.linkname = link;
+        }
+        apply_records(header, &self.global);
+        apply_records(header, &self.local);
+        header.pax_records.extend(self.global.iter().cloned());
+        header.pax_records.append(&mut self.local);
+    }
+}
+
+/// Parse GNU dumpdir data: NUL terminated records of status char followed by name,
+/// list ends with empty record.
+pub fn parse_dumpdir(data: &[u8]) -> Vec<(char, String)> {
+    data.split(|&b| b == 0)
+        .take_while(|r| !r.is_empty())
+        .map(|r| (r[0] as char, String::from_utf8_lossy(&r[1..]).into_owned()))
+        .collect()
+}
+
+/// GNU long name data is just NUL terminated string.
+fn gnu_long_value(data: &[u8]) -> String {
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    String::from_utf8_lossy(&data[..end]).into_owned()
+}
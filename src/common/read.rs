@@ -1,41 +1,680 @@
-use std::io::{Read, Seek, SeekFrom};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
 
 use crate::common::meta::PosixHeader;
 
-use super::BLOCK_SIZE;
-use super::meta::{Header, HeaderCheck};
+use crate::TarError;
+
+use super::{SkipData, BLOCK_SIZE};
+use super::meta::{ChecksumPolicy, FieldError, Header, HeaderCheck, HeaderIssue, HeaderProperty, HeaderType};
+use super::pax::{parse_dumpdir, PendingMeta};
 use super::offset_by_blocks;
 
+/// Count valid headers in source without building Header structs.
+///
+/// Walks blocks grid using only header size to skip data.
+/// Counting stops on the first not valid header same as HeadersParser does.
+pub fn count_entries<S: Read + Seek>(source: &mut S) -> io::Result<usize> {
+    let len = source.seek(SeekFrom::End(0))?;
+    source.rewind()?;
+    let mut count = 0;
+    let mut buffer = [0; BLOCK_SIZE];
+    loop {
+        match source.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(count),
+            Err(e) => return Err(e),
+        }
+        let ph = PosixHeader::from(0, buffer);
+        let size_overflow = ph.numeric_field(HeaderProperty::Size) == Err(FieldError::Overflow);
+        if ph.check() != HeaderCheck::Valid || size_overflow || ph.size() as u64 > len {
+            return Ok(count);
+        }
+        if !ph.typeflag().is_metadata() {
+            count += 1;
+        }
+        match i64::try_from(offset_by_blocks(ph.size())) {
+            Ok(shift) => source.seek(SeekFrom::Current(shift))?,
+            Err(_) => return Ok(count),
+        };
+    }
+}
+
+/// Archive statistics collected by `summarize`.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub struct ArchiveSummary {
+    /// Number of entries (metadata entries are not counted).
+    pub entries: usize,
+    /// Sum of entries data sizes.
+    pub total_data_bytes: u64,
+    /// Archive size including terminator and padding after it.
+    pub total_archive_bytes: u64,
+    /// Archive has symbolic link entries.
+    pub has_symlinks: bool,
+    /// Archive has character or block device entries.
+    pub has_devices: bool,
+}
+
+/// Collect archive statistics in one pass without keeping headers.
+pub fn summarize<S: Read + Seek>(source: &mut S) -> io::Result<ArchiveSummary> {
+    source.rewind()?;
+    let mut summary = ArchiveSummary::default();
+    let mut parser = HeadersParser::from(source);
+    for h in parser.by_ref() {
+        summary.entries += 1;
+        summary.total_data_bytes += h.size as u64;
+        summary.has_symlinks |= h.typeflag == HeaderType::Sym;
+        summary.has_devices |= matches!(h.typeflag, HeaderType::Chr | HeaderType::Blk);
+    }
+    parser.trailing_zero_blocks();
+    summary.total_archive_bytes = parser.offset as u64;
+    Ok(summary)
+}
+
+/// Commonly used blocking factors, most common first.
+const BLOCKING_FACTORS: [usize; 7] = [20, 10, 16, 8, 4, 2, 1];
+
+/// Guess blocking factor (record size in blocks) the archive was written with
+/// from the padding after terminator. Archive without padding gives 1,
+/// 20 (tar default) is returned when it is not clear.
+pub fn detect_blocking_factor<S: Read + Seek>(source: &mut S) -> usize {
+    let mut parser = HeadersParser::from(source);
+    parser.by_ref().count();
+    // Parser stops after the first terminator block
+    let used = parser.offset / BLOCK_SIZE + 1;
+    let len = match parser.source.seek(SeekFrom::End(0)) {
+        Ok(len) if (len as usize).is_multiple_of(BLOCK_SIZE) => len as usize / BLOCK_SIZE,
+        _ => return 20,
+    };
+    if len == used {
+        // Not padded at all
+        return 1;
+    }
+    if len < used {
+        return 20;
+    }
+    let padded_to = |factor: usize| used.div_ceil(factor) * factor == len;
+    BLOCKING_FACTORS.iter().copied().find(|&f| padded_to(f)).unwrap_or(20)
+}
+
+/// Headers of entries with given type only, e.g. just regular files.
+pub fn entries_of_type<S: Read + Seek>(source: &mut S, typeflag: HeaderType) -> impl Iterator<Item = Header> + '_ {
+    HeadersParser::from(source).filter(move |h| h.typeflag == typeflag)
+}
+
+/// Headers of entries with any of given types.
+pub fn entries_of_types<'a, S: Read + Seek>(
+    source: &'a mut S,
+    types: &'a [HeaderType],
+) -> impl Iterator<Item = Header> + 'a {
+    HeadersParser::from(source).filter(move |h| types.contains(&h.typeflag))
+}
+
+/// Archive format variants told apart by `detect_format`.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ArchiveFormat {
+    /// Old unix archive without magic.
+    V7,
+    /// POSIX ustar without extended headers.
+    Ustar,
+    /// Old GNU format: GNU magic or GNU-only typeflags.
+    Gnu,
+    /// POSIX ustar with PAX extended headers.
+    Pax,
+}
+
+/// Headers looked at by `detect_format`.
+const DETECT_FORMAT_HEADERS: usize = 16;
+
+/// GNU-only typeflags: long name and link, sparse, dumpdir, multi-volume and volume label.
+const GNU_TYPEFLAGS: [u8; 6] = [b'L', b'K', b'S', b'D', b'M', b'V'];
+
+/// Guess archive format by magic and typeflags of the first headers.
+/// GNU typeflags mean old GNU format even with POSIX magic, None if there are no valid headers.
+pub fn detect_format<S: Read + Seek>(source: &mut S) -> Option<ArchiveFormat> {
+    let headers = HeadersParser::from(source)
+        .checksum_policy(ChecksumPolicy::ChecksumOnly)
+        .emit_metadata(true)
+        .take(DETECT_FORMAT_HEADERS)
+        .collect::<Vec<Header>>();
+    let first = headers.first()?;
+    let format = if headers
+        .iter()
+        .any(|h| &h.magic() == b"ustar " || GNU_TYPEFLAGS.contains(&h.typeflag_byte()))
+    {
+        ArchiveFormat::Gnu
+    } else if headers.iter().any(|h| matches!(h.typeflag, HeaderType::Xhd | HeaderType::Xlg)) {
+        ArchiveFormat::Pax
+    } else if &first.magic() == b"ustar\0" {
+        ArchiveFormat::Ustar
+    } else {
+        ArchiveFormat::V7
+    };
+    Some(format)
+}
+
+/// Header at arbitrary byte offset if block there is a valid ustar header (checksum and magic),
+/// e.g. to find the start of archive embedded into other data. Source position is restored.
+pub fn try_header_at<S: Read + Seek>(source: &mut S, offset: u64) -> Option<Header> {
+    let position = source.stream_position().ok()?;
+    let mut buffer = [0; BLOCK_SIZE];
+    let read = source
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| source.read_exact(&mut buffer));
+    source.seek(SeekFrom::Start(position)).ok()?;
+    read.ok()?;
+    let ph = PosixHeader::from(offset as usize, buffer);
+    if ph.check() == HeaderCheck::Valid {
+        Some(Header::from(ph))
+    } else {
+        None
+    }
+}
+
+/// Offsets dividing archive into chunks of about `target_chunk_bytes` (the first chunk starts at 0,
+/// each offset starts the next one). Every offset is an entry boundary: the header block or
+/// the first of PAX and GNU metadata headers that belong to entry. Entry larger than target
+/// gets its own chunk, terminator is a part of the last chunk.
+pub fn split_points<S: Read + Seek>(source: &mut S, target_chunk_bytes: usize) -> Vec<usize> {
+    let mut points = Vec::new();
+    let mut chunk_start = 0;
+    let mut entry_start = None;
+    for h in HeadersParser::from(source).emit_metadata(true) {
+        let start = *entry_start.get_or_insert(h.offset);
+        if h.typeflag.is_metadata() {
+            continue;
+        }
+        entry_start = None;
+        let end = h.offset + BLOCK_SIZE + offset_by_blocks(h.size);
+        if end - chunk_start > target_chunk_bytes && start > chunk_start {
+            points.push(start);
+            chunk_start = start;
+        }
+    }
+    points
+}
+
+/// Entry size limit used by `read_data` and other functions keeping data in memory (256 MiB),
+/// larger entries should be streamed with `entry_reader`.
+pub const MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Data size limit for entries parsed in memory by parsers (PAX and GNU metadata, dumpdir listings),
+/// data of larger ones is skipped.
+pub const MAX_METADATA_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Read exactly `size` bytes, buffer grows with data actually read,
+/// so size from broken header could not cause huge allocation.
+pub(crate) fn read_to_vec<R: Read>(source: &mut R, size: usize) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    source.by_ref().take(size as u64).read_to_end(&mut data)?;
+    if data.len() < size {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    Ok(data)
+}
+
+/// Fail before allocating or reading anything for entries declaring size above limit.
+fn check_entry_size(header: &Header, max_size: u64) -> io::Result<()> {
+    if header.size as u64 > max_size {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("entry size {} exceeds limit {}", header.size, max_size),
+        ));
+    }
+    Ok(())
+}
+
+/// Read entry data from source (header offset is used to locate it).
+/// Entries larger than `MAX_ENTRY_SIZE` give `InvalidData` error, see `read_data_limited` and `entry_reader`.
+pub fn read_data<S: Read + Seek>(source: &mut S, header: &Header) -> io::Result<Vec<u8>> {
+    read_data_limited(source, header, MAX_ENTRY_SIZE)
+}
+
+/// Read entry data refusing entries with size above `max_size` (broken or malicious headers
+/// could declare any size, so data buffer is not allocated for them).
+pub fn read_data_limited<S: Read + Seek>(source: &mut S, header: &Header, max_size: u64) -> io::Result<Vec<u8>> {
+    check_entry_size(header, max_size)?;
+    source.seek(SeekFrom::Start((header.offset + BLOCK_SIZE) as u64))?;
+    read_to_vec(source, header.size)
+}
+
+/// Read up to `len` bytes of entry data starting from `start` byte of it (e.g. to serve range requests).
+/// Reading stops at the end of entry data, `start` beyond it gives `InvalidInput` error.
+pub fn read_data_range<S: Read + Seek>(source: &mut S, header: &Header, start: usize, len: usize) -> io::Result<Vec<u8>> {
+    if start > header.size {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "range start is beyond entry data"));
+    }
+    source.seek(SeekFrom::Start((header.offset + BLOCK_SIZE + start) as u64))?;
+    read_to_vec(source, len.min(header.size - start))
+}
+
+/// Reader limited to entry data, allows to stream data without loading it into memory,
+/// so entries of any size are accepted (see `entry_reader_limited`).
+pub fn entry_reader<'a, S: Read + Seek>(
+    source: &'a mut S,
+    header: &Header,
+) -> io::Result<io::Take<&'a mut S>> {
+    entry_reader_limited(source, header, u64::MAX)
+}
+
+/// Reader of entry data refusing entries with size above `max_size`.
+pub fn entry_reader_limited<'a, S: Read + Seek>(
+    source: &'a mut S,
+    header: &Header,
+    max_size: u64,
+) -> io::Result<io::Take<&'a mut S>> {
+    check_entry_size(header, max_size)?;
+    source.seek(SeekFrom::Start((header.offset + BLOCK_SIZE) as u64))?;
+    Ok(source.take(header.size as u64))
+}
+
+/// Read entry data as UTF-8 text, invalid UTF-8 is reported as `InvalidData` error.
+pub fn read_data_string<S: Read + Seek>(source: &mut S, header: &Header) -> io::Result<String> {
+    let data = read_data(source, header)?;
+    String::from_utf8(data).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Read entry data as text, invalid UTF-8 sequences are replaced.
+pub fn read_data_string_lossy<S: Read + Seek>(source: &mut S, header: &Header) -> io::Result<String> {
+    let data = read_data(source, header)?;
+    Ok(String::from_utf8_lossy(&data).into_owned())
+}
+
+/// Read all entries with their data in archive order, metadata entries (PAX, GNU long names) are
+/// consumed by parser. Whole archive content is kept in memory, use `entry_reader` for large archives.
+pub fn read_all<S: Read + Seek>(source: &mut S) -> io::Result<Vec<(Header, Vec<u8>)>> {
+    let headers = HeadersParser::from(source).collect::<Vec<Header>>();
+    headers
+        .into_iter()
+        .map(|h| read_data(source, &h).map(|data| (h, data)))
+        .collect()
+}
+
+/// Fill block from source, returns number of bytes read (less than block size only at the end of source).
+pub(crate) fn read_block<S: Read>(source: &mut S, buffer: &mut [u8; BLOCK_SIZE]) -> io::Result<usize> {
+    let mut got = 0;
+    while got < BLOCK_SIZE {
+        match source.read(&mut buffer[got..]) {
+            Ok(0) => break,
+            Ok(n) => got += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(got)
+}
+
+/// Defines which zero blocks end iteration and which archive ends are reported by `is_clean`.
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+pub enum TerminatorPolicy {
+    /// Standard terminator of two zero blocks, lone zero block followed by header is skipped
+    /// and reported, archive ending with less than two zero blocks is reported too.
+    RequireTwo,
+    /// Single zero block ends iteration (as some GNU tar versions write), archive without
+    /// terminator is reported.
+    AcceptOne,
+    /// Iteration ends at the first zero block or at the end of source, nothing is reported.
+    #[default]
+    AcceptAny,
+}
+
+impl TerminatorPolicy {
+    /// Zero blocks expected at the end of archive.
+    fn required_zeroes(&self) -> u8 {
+        match self {
+            TerminatorPolicy::RequireTwo => 2,
+            TerminatorPolicy::AcceptOne => 1,
+            TerminatorPolicy::AcceptAny => 0,
+        }
+    }
+}
+
 /// Extracts tar Headers from some source.
 #[derive(Debug)]
 pub struct HeadersParser<'a, S> {
     offset: usize,
+    start: u64,
+    source_len: u64,
     source: &'a mut S,
     iter_valid_headers: usize,
     iter_invalid_headers: usize,
     iter_zeroes: u8,
+    checksum_policy: ChecksumPolicy,
+    stop_at_terminator: bool,
+    continue_past_terminator: bool,
+    terminator_policy: TerminatorPolicy,
+    terminated: bool,
+    emit_metadata: bool,
+    strict: bool,
+    unpadded_data: bool,
+    resync: bool,
+    lenient_numbers: bool,
+    verify_padding: bool,
+    padding_error: Option<usize>,
+    names: HashSet<String>,
+    partial_block: Option<usize>,
+    capture_data: bool,
+    capture_limit: u64,
+    captured: Option<Vec<u8>>,
+    pending: PendingMeta,
+    on_invalid: Option<InvalidHook<'a>>,
+    #[cfg(feature = "encoding")]
+    name_encoding: Option<&'static encoding_rs::Encoding>,
+}
+
+/// Observer of blocks rejected by checksum policy: header offset, header parsed as is and its status.
+type InvalidFn<'a> = dyn FnMut(usize, &Header, HeaderCheck) + 'a;
+
+struct InvalidHook<'a>(Box<InvalidFn<'a>>);
+
+impl std::fmt::Debug for InvalidHook<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InvalidHook")
+    }
 }
 
 impl<'a, T: Read + Seek> HeadersParser<'a, T> {
-    fn from(reader: &'a mut T) -> HeadersParser<'a, T> {
-        reader.seek(SeekFrom::Start(0));
+    pub fn from(reader: &'a mut T) -> HeadersParser<'a, T> {
+        HeadersParser::from_at(reader, 0)
+    }
+
+    /// Parse archive embedded into larger file (e.g. self-extracting blob) starting at `start`.
+    /// Header offsets are related to source start, so `read_data` and others work as usual.
+    pub fn from_at(reader: &'a mut T, start: u64) -> HeadersParser<'a, T> {
+        let source_len = reader.seek(SeekFrom::End(0)).unwrap_or(u64::MAX);
+        let _ = reader.seek(SeekFrom::Start(start));
         HeadersParser {
             offset: 0,
+            start,
+            source_len,
             source: reader,
             iter_valid_headers: 0,
             iter_invalid_headers: 0,
             iter_zeroes: 0,
+            checksum_policy: ChecksumPolicy::default(),
+            stop_at_terminator: false,
+            continue_past_terminator: false,
+            terminator_policy: TerminatorPolicy::default(),
+            terminated: false,
+            emit_metadata: false,
+            strict: false,
+            unpadded_data: false,
+            resync: false,
+            lenient_numbers: false,
+            verify_padding: false,
+            padding_error: None,
+            names: HashSet::new(),
+            partial_block: None,
+            capture_data: false,
+            capture_limit: MAX_ENTRY_SIZE,
+            captured: None,
+            pending: PendingMeta::default(),
+            on_invalid: None,
+            #[cfg(feature = "encoding")]
+            name_encoding: None,
+        }
+    }
+
+    /// Set which headers should be yielded by iterator (default is strict).
+    pub fn checksum_policy(mut self, policy: ChecksumPolicy) -> Self {
+        self.checksum_policy = policy;
+        self
+    }
+
+    /// Stop reading source at the first zero block (archive terminator).
+    /// Everything after it is ignored, so trailing garbage would not be treated as broken headers.
+    pub fn stop_at_terminator(mut self, stop: bool) -> Self {
+        self.stop_at_terminator = stop;
+        self
+    }
+
+    /// Skip zero blocks and keep looking for headers after archive terminator,
+    /// allows to find entries of several concatenated archives. Ignored if `stop_at_terminator` is set.
+    pub fn continue_past_terminator(mut self, proceed: bool) -> Self {
+        self.continue_past_terminator = proceed;
+        self
+    }
+
+    /// Set which zero blocks are treated as archive terminator (default accepts any end of archive).
+    pub fn terminator_policy(mut self, policy: TerminatorPolicy) -> Self {
+        self.terminator_policy = policy;
+        self
+    }
+
+    /// Yield PAX and GNU metadata pseudo entries (x, g, L, K) too.
+    /// By default they are consumed silently and only applied to the following entries.
+    pub fn emit_metadata(mut self, emit: bool) -> Self {
+        self.emit_metadata = emit;
+        self
+    }
+
+    /// Validate headers structure and record found problems in `Header::issues`.
+    /// Repeated names are reported too, so use it for fresh archives only (not appended ones).
+    /// Headers with issues are still yielded.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Read padding after entry data instead of skipping it and check that it is all zeroes
+    /// (junk there means corruption or hidden content), see `check_padding`.
+    pub fn verify_padding(mut self, verify: bool) -> Self {
+        self.verify_padding = verify;
+        self
+    }
+
+    /// Expect entry data without padding to blocks (next header starts right after `size` bytes).
+    /// It is not spec compliant but some embedded tools write archives this way.
+    pub fn unpadded_data(mut self, unpadded: bool) -> Self {
+        self.unpadded_data = unpadded;
+        self
+    }
+
+    /// Recovery mode: after broken (or zeroed) header keep scanning following blocks
+    /// one by one until the next acceptable header is found, instead of stopping.
+    /// Data of damaged entry is lost, iteration ends only at the end of source.
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.resync = resync;
+        self
+    }
+
+    /// Read size and mtime fields with junk after digits (e.g. `644 garbage`) from the leading
+    /// octal run instead of treating them as 0.
+    pub fn lenient_numbers(mut self, lenient: bool) -> Self {
+        self.lenient_numbers = lenient;
+        self
+    }
+
+    /// Call `hook` for every block rejected by checksum policy (e.g. for logging or metrics),
+    /// zero blocks of terminator are not reported.
+    /// Iteration itself is not affected, use `resync` to go on after such blocks.
+    pub fn on_invalid<F: FnMut(usize, &Header, HeaderCheck) + 'a>(mut self, hook: F) -> Self {
+        self.on_invalid = Some(InvalidHook(Box::new(hook)));
+        self
+    }
+
+    /// Decode names (name, linkname, uname, gname) with specified encoding instead of lossy UTF-8.
+    /// Useful for legacy archives created with Shift-JIS, Latin-1 and so on.
+    #[cfg(feature = "encoding")]
+    pub fn name_encoding(mut self, encoding: &'static encoding_rs::Encoding) -> Self {
+        self.name_encoding = Some(encoding);
+        self
+    }
+
+    /// Stop iteration right after the first header matching predicate (it is still yielded).
+    /// Rest of the source is not read at all.
+    pub fn until<P: FnMut(&Header) -> bool>(self, predicate: P) -> Until<Self, P> {
+        Until {
+            inner: self,
+            predicate,
+            done: false,
+        }
+    }
+
+    /// Yield headers together with position in source, e.g. for progress bars.
+    /// Total is the source length at the moment of call.
+    pub fn with_progress(self) -> WithProgress<'a, T> {
+        let current = self.source.stream_position().unwrap_or_default();
+        let total = self.source.seek(SeekFrom::End(0)).unwrap_or_default().saturating_sub(self.start);
+        let _ = self.source.seek(SeekFrom::Start(current));
+        WithProgress { inner: self, total }
+    }
+
+    /// Largest entry data kept by `next_with_data` (`MAX_ENTRY_SIZE` by default),
+    /// data of larger entries is skipped and given as empty.
+    pub fn capture_limit(mut self, limit: u64) -> Self {
+        self.capture_limit = limit;
+        self
+    }
+
+    /// Next header together with its data read on the way (no extra seek back is needed).
+    /// Entries above `capture_limit` come with empty data, use `entry_reader` for them.
+    /// Metadata entries (PAX, GNU long names) are never captured.
+    pub fn next_with_data(&mut self) -> Option<(Header, Vec<u8>)> {
+        self.captured = None;
+        self.capture_data = true;
+        let h = self.next();
+        self.capture_data = false;
+        let data = self.captured.take().unwrap_or_default();
+        h.map(|h| (h, data))
+    }
+
+    /// Number of zero blocks found after the last entry, should be called after iteration.
+    /// Remaining zero blocks are read from source, first non zero block is left unread.
+    ///
+    /// 0 means that archive is truncated, 1 is minimal terminator, 2 is standard one
+    /// and anything above is padding (e.g. up to the blocking factor).
+    pub fn trailing_zero_blocks(&mut self) -> u8 {
+        let mut buffer = [0; BLOCK_SIZE];
+        while self.source.read_exact(&mut buffer).is_ok() {
+            if buffer.iter().any(|&b| b != 0) {
+                let _ = self.source.seek(SeekFrom::Current(-(BLOCK_SIZE as i64)));
+                break;
+            }
+            self.offset += BLOCK_SIZE;
+            self.iter_zeroes = self.iter_zeroes.saturating_add(1);
+        }
+        self.iter_zeroes
+    }
+
+    /// Number of bytes in source after current parser position, should be called after iteration
+    /// (and usually after `trailing_zero_blocks`). Non zero value means unexpected trailing content.
+    pub fn trailing_bytes(&mut self) -> u64 {
+        let len = self.source.seek(SeekFrom::End(0)).unwrap_or_default();
+        let position = self.start + self.offset as u64;
+        let _ = self.source.seek(SeekFrom::Start(position));
+        len.saturating_sub(position)
+    }
+
+    /// Tells whether source ended cleanly at block boundary, should be called after iteration.
+    /// Iteration just stops in both cases, but truncated source (e.g. broken download)
+    /// gives `UnexpectedEof` with number of bytes found in the last incomplete block.
+    pub fn check_eof(&self) -> Result<(), TarError> {
+        match self.partial_block {
+            Some(got) => Err(TarError::UnexpectedEof { got }),
+            None => Ok(()),
+        }
+    }
+
+    /// Tells whether padding after entries data was all zeroes, should be called after iteration
+    /// with `verify_padding` enabled. Error holds offset of the first non zero padding byte.
+    pub fn check_padding(&self) -> Result<(), TarError> {
+        match self.padding_error {
+            Some(offset) => Err(TarError::NonZeroPadding { offset }),
+            None => Ok(()),
+        }
+    }
+
+    /// Read the next whole block, incomplete block at the end of source is remembered for `check_eof`.
+    fn next_block(&mut self, buffer: &mut [u8; BLOCK_SIZE]) -> Option<()> {
+        match read_block(self.source, buffer).ok()? {
+            BLOCK_SIZE => Some(()),
+            0 => None,
+            got => {
+                self.partial_block = Some(got);
+                None
+            }
+        }
+    }
+
+    /// Header size field is representable and is not beyond the whole source.
+    fn size_fits(&self, ph: &PosixHeader) -> bool {
+        ph.numeric_field(HeaderProperty::Size) != Err(FieldError::Overflow) && ph.size() as u64 <= self.source_len
+    }
+
+    /// Current position in source, header offsets are related to source start not to archive one.
+    fn source_offset(&self) -> usize {
+        self.start as usize + self.offset
+    }
+
+    /// Check if the second terminator block follows the first one, it is consumed if so.
+    /// None means that source ends right after the first zero block.
+    fn second_zero_block(&mut self) -> Option<bool> {
+        let mut buffer = [0; BLOCK_SIZE];
+        let got = read_block(self.source, &mut buffer).unwrap_or_default();
+        if got == BLOCK_SIZE && buffer.iter().all(|&b| b == 0) {
+            self.offset += BLOCK_SIZE;
+            self.iter_zeroes = self.iter_zeroes.saturating_add(1);
+            return Some(true);
+        }
+        let _ = self.source.seek(SeekFrom::Current(-(got as i64)));
+        if got < BLOCK_SIZE {
+            None
+        } else {
+            Some(false)
+        }
+    }
+
+    /// True if no invalid headers were found so far.
+    pub fn is_clean(&self) -> bool {
+        self.iter_invalid_headers == 0
+    }
+
+    /// Bytes occupied by entry data in source.
+    fn data_span(&self, size: usize) -> usize {
+        if self.unpadded_data {
+            size
+        } else {
+            offset_by_blocks(size)
+        }
+    }
+
+    /// Build Header respecting parser options (names encoding, strict checks).
+    fn header(&self, ph: PosixHeader) -> Header {
+        let issues = if self.strict { ph.structure_issues() } else { Vec::new() };
+        let lenient = |range| ph.numeric_field_lenient(range).ok().filter(|_| self.lenient_numbers);
+        let (size, mtime) = (lenient(HeaderProperty::Size), lenient(HeaderProperty::Mtime));
+
+        #[cfg(feature = "encoding")]
+        let mut h = match self.name_encoding {
+            Some(encoding) => Header::from_encoded(ph, encoding),
+            None => Header::from(ph),
+        };
+        #[cfg(not(feature = "encoding"))]
+        let mut h = Header::from(ph);
+        h.issues = issues;
+        if let Some(size) = size {
+            h.size = size as usize;
+            h.size_field = Ok(size);
         }
+        if let Some(mtime) = mtime {
+            h.mtime = mtime as u128;
+            h.mtime_field = Ok(mtime);
+        }
+        h
     }
 
     /// Read any bytes as block.
     /// It is possible that we could have invalid header somewhere in the middle but with proper size attribute,
     /// thus it would be possible to shift to the next valid header.
-    fn next_any(&mut self) -> Option<Header> {
+    /// Also returns if header is acceptable by checksum policy.
+    pub(crate) fn next_any(&mut self) -> Option<(Header, bool)> {
+        if self.terminated {
+            return None;
+        }
         let mut buffer = [0; BLOCK_SIZE];
-        // Assuming it would shift position at number of buffer
-        self.source.read_exact(&mut buffer).ok()?;
-        self.offset += BLOCK_SIZE;
+        self.next_block(&mut buffer)?;
 
         // print!("BUFFER: ");
         // for i in 0..BLOCK_SIZE {
@@ -43,69 +682,278 @@ impl<'a, T: Read + Seek> HeadersParser<'a, T> {
         // }
         // println!("");
 
-        let ph = PosixHeader::from(self.offset, buffer);
-        ///TODO Should change approach and check validation first
-
-        let h = Header::from(ph);
-        let size = h.size;
-        let shift = offset_by_blocks(size);
+        let ph = PosixHeader::from(self.source_offset(), buffer);
+        self.offset += BLOCK_SIZE;
+        // TODO Should change approach and check validation first
+        let size_overflow = ph.numeric_field(HeaderProperty::Size) == Err(FieldError::Overflow);
+        let mut accepted = self.checksum_policy.accepts(&ph) && self.size_fits(&ph);
+        let mut h = self.header(ph);
+        let mut consumed = 0;
+        if accepted {
+            if h.typeflag.is_metadata() && check_entry_size(&h, MAX_METADATA_SIZE).is_ok() {
+                // Truncated metadata is used as far as it goes
+                let mut data = Vec::new();
+                self.source.by_ref().take(h.size as u64).read_to_end(&mut data).ok()?;
+                consumed = data.len();
+                if consumed < h.size {
+                    h.issues.push(HeaderIssue::TruncatedData);
+                }
+                self.pending.consume(h.typeflag, &data);
+            } else if !h.typeflag.is_metadata() {
+                self.pending.apply(&mut h);
+                if self.strict && !self.names.insert(h.name.clone()) {
+                    h.issues.push(HeaderIssue::DuplicateName);
+                }
+                let dumpdir = h.typeflag == HeaderType::Dumpdir && h.size as u64 <= MAX_METADATA_SIZE;
+                let capture = self.capture_data && h.size as u64 <= self.capture_limit;
+                if dumpdir || capture {
+                    let data = read_to_vec(self.source, h.size).ok()?;
+                    consumed = h.size;
+                    if dumpdir {
+                        h.dumpdir = parse_dumpdir(&data);
+                    }
+                    if capture {
+                        self.captured = Some(data);
+                    }
+                }
+            }
+        }
+        // Size from broken or hostile header (or PAX record) could be beyond the whole source,
+        // such header is not accepted and nothing is skipped by its size
+        let oversized = size_overflow || h.size as u64 > self.source_len;
+        accepted &= !oversized;
+        let shift = if oversized { 0 } else { self.data_span(h.size) };
 
         //println!("File size {} shift {}", size, shift);
 
         self.offset += shift;
-        self.source.seek(SeekFrom::Current(shift as i64));
+        if self.verify_padding && accepted && shift > h.size {
+            self.skip_data((h.size - consumed.min(h.size)) as u64).ok()?;
+            let mut padding = vec![0; shift - h.size];
+            if self.source.read_exact(&mut padding).is_ok() && self.padding_error.is_none() {
+                self.padding_error = padding
+                    .iter()
+                    .position(|&b| b != 0)
+                    .map(|i| h.offset + BLOCK_SIZE + h.size + i);
+            }
+        } else {
+            self.skip_data((shift - consumed) as u64).ok()?;
+        }
 
         // Now lets collect some stats
         match &h.check {
+            HeaderCheck::Valid if oversized => {
+                self.iter_invalid_headers += 1;
+            }
             HeaderCheck::Valid => {
                 self.iter_valid_headers += 1;
-                if self.iter_zeroes > 0 {
+                if self.continue_past_terminator {
+                    // Next archive starts
+                    self.iter_zeroes = 0;
+                } else if self.iter_zeroes > 0 {
                     // Valid header could not be after zero header - consider this as an error.
                     self.iter_invalid_headers += 1;
                 }
             }
-            HeaderCheck::Invalid { not_ustar } => {
+            HeaderCheck::Invalid { .. } => {
                 self.iter_invalid_headers += 1;
             }
             HeaderCheck::Zeroes => {
-                if self.iter_zeroes > 2 {
-                    // Only 2 zero headers allowed
+                if self.iter_zeroes > 2 && !self.continue_past_terminator {
+                    // Only 2 zero headers allowed (padding between concatenated archives is fine)
                     self.iter_invalid_headers += 1;
                 }
-                self.iter_zeroes += 1;
+                self.iter_zeroes = self.iter_zeroes.saturating_add(1);
+                self.terminated = self.stop_at_terminator;
             }
         }
-        Some(h)
+        Some((h, accepted))
+    }
+}
+
+/// Source is seekable so skipping takes O(1) regardless of data size.
+impl<'a, T: Read + Seek> SkipData for HeadersParser<'a, T> {
+    fn skip_data(&mut self, n: u64) -> io::Result<()> {
+        let n = i64::try_from(n).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "skip is too large"))?;
+        self.source.seek(SeekFrom::Current(n)).map(|_| ())
     }
 }
 
 impl<'a, T: Read + Seek> Iterator for HeadersParser<'a, T> {
     type Item = Header;
 
-    /// Iterate only over valid blocks.
+    /// Iterate only over blocks accepted by checksum policy (valid only by default).
     /// Last two blocks are just zeroes so we just ignore them (not valid).
     fn next(&mut self) -> Option<Self::Item> {
-        let h = self.next_any()?;
+        loop {
+            let Some((h, accepted)) = self.next_any() else {
+                if !self.terminated {
+                    // End of source, check that archive was terminated properly
+                    if self.iter_zeroes < self.terminator_policy.required_zeroes() {
+                        self.iter_invalid_headers += 1;
+                    }
+                    self.terminated = true;
+                }
+                return None;
+            };
+
+            if h.check == HeaderCheck::Zeroes && self.continue_past_terminator {
+                continue;
+            }
+            if h.check == HeaderCheck::Zeroes
+                && self.terminator_policy == TerminatorPolicy::RequireTwo
+                && self.iter_zeroes == 1
+            {
+                match self.second_zero_block() {
+                    Some(true) => {}
+                    Some(false) => {
+                        // Lone zero block in the middle of archive is not a terminator
+                        self.iter_invalid_headers += 1;
+                        self.iter_zeroes = 0;
+                        continue;
+                    }
+                    None => {
+                        self.iter_invalid_headers += 1;
+                        self.terminated = true;
+                        return None;
+                    }
+                }
+            }
+            if !accepted {
+                match self.on_invalid.as_mut() {
+                    Some(InvalidHook(hook)) if h.check != HeaderCheck::Zeroes => hook(h.offset, &h, h.check),
+                    _ => {}
+                }
+                if !self.resync {
+                    return None;
+                }
+                // Size of broken header is garbage, continue right after its block
+                self.offset = h.offset - self.start as usize + BLOCK_SIZE;
+                self.source.seek(SeekFrom::Start(self.start + self.offset as u64)).ok()?;
+                continue;
+            }
+            if h.typeflag.is_metadata() && !self.emit_metadata {
+                continue;
+            }
+            return Some(h);
+        }
+    }
+
+    /// Walk to the last entry using only raw blocks, Header is built just for the final one.
+    /// Entries affected by metadata (PAX, GNU long names) are still parsed completely.
+    fn last(mut self) -> Option<Self::Item> {
+        let mut last = None;
+        let mut last_raw = None;
+        while !self.terminated {
+            let mut buffer = [0; BLOCK_SIZE];
+            if self.next_block(&mut buffer).is_none() {
+                break;
+            }
+            let ph = PosixHeader::from(self.source_offset(), buffer);
+            let typeflag = ph.typeflag();
+            let plain = self.checksum_policy.accepts(&ph)
+                && ph.check() != HeaderCheck::Zeroes
+                && !typeflag.is_metadata()
+                && typeflag != HeaderType::Dumpdir
+                && !self.strict
+                && !self.lenient_numbers
+                && !self.verify_padding
+                && self.size_fits(&ph)
+                && self.pending.is_empty();
+            if plain {
+                let shift = self.data_span(ph.size());
+                self.skip_data(shift as u64).ok()?;
+                self.offset += BLOCK_SIZE + shift;
+                last_raw = Some(ph);
+                last = None;
+                continue;
+            }
+
+            self.source.seek(SeekFrom::Current(-(BLOCK_SIZE as i64))).ok()?;
+            match self.next() {
+                Some(h) => {
+                    last = Some(h);
+                    last_raw = None;
+                }
+                None => break,
+            }
+        }
+        last.or_else(|| last_raw.map(|ph| self.header(ph)))
+    }
+}
 
-        if let HeaderCheck::Valid = h.check {
-            Some(h)
+/// Position of parser in source.
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Progress {
+    /// Source bytes passed so far (up to the end of yielded entry data)
+    pub bytes_read: u64,
+    /// Source length
+    pub total_bytes: u64,
+}
+
+impl Progress {
+    /// Part of source passed in percents.
+    pub fn percent(&self) -> f64 {
+        if self.total_bytes == 0 {
+            100.0
         } else {
-            None
+            self.bytes_read as f64 * 100.0 / self.total_bytes as f64
+        }
+    }
+}
+
+/// Headers iterator with progress info, see `HeadersParser::with_progress`.
+#[derive(Debug)]
+pub struct WithProgress<'a, S> {
+    inner: HeadersParser<'a, S>,
+    total: u64,
+}
+
+impl<'a, S: Read + Seek> Iterator for WithProgress<'a, S> {
+    type Item = (Header, Progress);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let h = self.inner.next()?;
+        let progress = Progress {
+            bytes_read: self.inner.offset as u64,
+            total_bytes: self.total,
+        };
+        Some((h, progress))
+    }
+}
+
+/// Headers iterator that stops after predicate matches, see `HeadersParser::until`.
+#[derive(Debug)]
+pub struct Until<I, P> {
+    inner: I,
+    predicate: P,
+    done: bool,
+}
+
+impl<I: Iterator<Item = Header>, P: FnMut(&Header) -> bool> Iterator for Until<I, P> {
+    type Item = Header;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
+        let h = self.inner.next()?;
+        self.done = (self.predicate)(&h);
+        Some(h)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::env;
     use std::fs::File;
-    use std::io::{Read, Seek, SeekFrom};
-    use std::path::{Path, PathBuf};
+    use std::io::Cursor;
 
     use hamcrest2::prelude::*;
 
     use super::*;
     use super::super::meta::*;
+    use super::super::testutil::test_resources_path;
+    use super::super::stream::StreamParser;
 
     #[test]
     fn zero_header_validation() {
@@ -115,24 +963,121 @@ mod tests {
         assert_that!(header.validate(), equal_to(HeaderCheck::Zeroes));
     }
 
-    fn test_resources_path() -> PathBuf {
-        let basedir = env::var("CARGO_MANIFEST_DIR").unwrap();
-        Path::new(&basedir).join("test")
+    #[test]
+    fn header_to_bytes() {
+        let block = v7_header("bytes.txt", 10);
+        let header = PosixHeader::from(0, block);
+
+        assert_that!(header.to_bytes().to_vec(), equal_to(block.to_vec()));
+        let raw: [u8; BLOCK_SIZE] = (&header).into();
+        assert_that!(raw.to_vec(), equal_to(block.to_vec()));
+    }
+
+    #[test]
+    fn header_edit_and_repair() {
+        let mut header = PosixHeader::from(0, v7_header("edit.txt", 10));
+        header.set_field(HeaderProperty::Name, b"edited.txt");
+        assert_that!(header.check(), equal_to(HeaderCheck::Invalid { not_ustar: false }));
+
+        header.repair_checksum();
+        assert_that!(header.check(), equal_to(HeaderCheck::Invalid { not_ustar: true }));
+
+        let reparsed = PosixHeader::from(0, header.to_bytes());
+        assert_that!(reparsed.extract_string(HeaderProperty::Name), equal_to("edited.txt".to_string()));
+        assert_that!(reparsed.check(), equal_to(HeaderCheck::Invalid { not_ustar: true }));
+    }
+
+    #[test]
+    fn effective_type_directory() {
+        let mut ph = PosixHeader::from(0, v7_header("some_dir/", 0));
+        ph.set_field(HeaderProperty::Typeflag, b" ");
+        let h = Header::from(ph);
+
+        assert_that!(h.typeflag, equal_to(HeaderType::Unknown));
+        assert_that!(h.effective_type(), equal_to(HeaderType::Dir));
+
+        let h = Header::from(PosixHeader::from(0, v7_header("old_dir/", 0)));
+        assert_that!(h.effective_type(), equal_to(HeaderType::Dir));
+    }
+
+    #[test]
+    fn effective_type_symlink() {
+        let mut ph = PosixHeader::from(0, v7_header("link", 0));
+        ph.set_field(HeaderProperty::Typeflag, b" ");
+        ph.set_field(HeaderProperty::Linkname, b"target.txt");
+        let h = Header::from(ph);
+        assert_that!(h.effective_type(), equal_to(HeaderType::Sym));
+
+        let h = Header::from(PosixHeader::from(0, v7_header("file.txt", 0)));
+        assert_that!(h.effective_type(), equal_to(HeaderType::Reg));
+    }
+
+    #[test]
+    fn checksum_field_layouts() {
+        let block = v7_header("checksum.txt", 1);
+        let header = PosixHeader::from(0, block);
+        let expected = header.checksum().unwrap();
+        let digits = format!("{:06o}", expected);
+
+        for layout in &["{}\0 ", "{} \0", " {}\0", "{}  "] {
+            let field = layout.replace("{}", &digits);
+            let mut header = PosixHeader::from(0, block);
+            header.set_field(HeaderProperty::Chksum, field.as_bytes());
+
+            assert_that!(header.checksum(), equal_to(Some(expected)));
+            assert_that!(header.check(), equal_to(HeaderCheck::Invalid { not_ustar: true }));
+        }
+
+        let mut header = PosixHeader::from(0, block);
+        header.set_field(HeaderProperty::Chksum, b"        ");
+        assert_that!(header.checksum(), equal_to(None));
+        assert_that!(header.check(), equal_to(HeaderCheck::Invalid { not_ustar: false }));
+
+        // Junk before or after terminator
+        for field in [format!("{}x\0", &digits[..5]), format!("{}\0x", digits)] {
+            let mut header = PosixHeader::from(0, block);
+            header.set_field(HeaderProperty::Chksum, field.as_bytes());
+            assert_that!(header.checksum(), equal_to(None));
+            assert_that!(header.check(), equal_to(HeaderCheck::Invalid { not_ustar: false }));
+            assert_that!(header.invalid_reason(), equal_to(Some(InvalidReason::MalformedChecksum)));
+        }
+
+        let header = PosixHeader::from(0, block);
+        assert_that!(header.invalid_reason(), equal_to(Some(InvalidReason::NotUstar)));
+        let mut header = PosixHeader::from(0, block);
+        header.set_field(HeaderProperty::Name, b"changed.txt");
+        assert_that!(header.invalid_reason(), equal_to(Some(InvalidReason::ChecksumMismatch)));
+    }
+
+    #[test]
+    fn signed_checksum_kind() {
+        use super::super::testutil::header;
+
+        let ph = header("plain.txt", b'0', 0);
+        assert_that!(ph.checksum_kind(), equal_to(Some(ChecksumKind::Both)));
+
+        let mut ph = header("", b'0', 0);
+        ph.set_field(HeaderProperty::Name, "naïve.txt".as_bytes());
+        ph.repair_checksum();
+        assert_that!(ph.checksum_kind(), equal_to(Some(ChecksumKind::Unsigned)));
+
+        let signed_sum: isize = ph.to_bytes()[..HEADER_SIZE]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if HeaderProperty::Chksum.contains(&i) { b' ' as isize } else { b as i8 as isize })
+            .sum();
+        ph.set_field(HeaderProperty::Chksum, format!("{:06o}\0 ", signed_sum).as_bytes());
+        assert_that!(ph.check(), equal_to(HeaderCheck::Valid));
+        assert_that!(ph.checksum_kind(), equal_to(Some(ChecksumKind::Signed)));
+
+        ph.set_field(HeaderProperty::Chksum, b"000001\0 ");
+        assert_that!(ph.checksum_kind(), equal_to(None));
     }
 
     fn basic_header_validation(h: &Header) {
         assert_that!(h.check, equal_to(HeaderCheck::Valid));
         assert_that!(h.typeflag, not(equal_to(HeaderType::Unknown)));
-
-        // assert_that!(
-        //     &h.source().extract(HeaderProperty::Magic).to_vec(),
-        //     contains(HEADER_MAGIC.to_vec())
-        // );
-
-        // assert_that!(
-        //     &h.source().extract(HeaderProperty::Version).to_vec(),
-        //     contains(HEADER_VERSION.to_vec())
-        // );
+        assert_that!(&h.magic()[..5], equal_to(&b"ustar"[..]));
     }
 
     #[test]
@@ -147,18 +1092,18 @@ mod tests {
         assert_eq!(headers.len(), 4);
 
         let file_1 = &headers[0];
-        basic_header_validation(&file_1);
+        basic_header_validation(file_1);
         assert_that!(file_1.size, equal_to(512));
 
         let file_2 = &headers[1];
-        basic_header_validation(&file_2);
+        basic_header_validation(file_2);
         assert_that!(file_2.size, less_than(512));
 
         let file_3 = &headers[2];
-        basic_header_validation(&file_3);
+        basic_header_validation(file_3);
         assert_that!(file_3.size, greater_than(512));
         let file_4 = &headers[3];
-        basic_header_validation(&file_4);
+        basic_header_validation(file_4);
         assert_that!(file_4.size, less_than(512));
     }
 
@@ -174,17 +1119,892 @@ mod tests {
         assert_eq!(headers.len(), 3);
 
         let file_1 = &headers[0];
-        basic_header_validation(&file_1);
+        basic_header_validation(file_1);
         assert_that!(file_1.size, greater_than(0));
         let mut prev_size = file_1.size;
 
         let file_2 = &headers[1];
-        basic_header_validation(&file_2);
+        basic_header_validation(file_2);
         assert_that!(file_2.size, greater_than(prev_size));
         prev_size = file_2.size;
 
         let file_3 = &headers[2];
-        basic_header_validation(&file_3);
+        basic_header_validation(file_3);
         assert_that!(file_3.size, greater_than(prev_size));
     }
+
+    #[test]
+    fn last_entry_without_collect() {
+        let mut file = File::open(test_resources_path().join("files_append_test.tar")).unwrap();
+        let expected = HeadersParser::from(&mut file).collect::<Vec<Header>>().pop().unwrap();
+
+        let last = HeadersParser::from(&mut file).last().unwrap();
+        assert_that!(last.header_offset(), equal_to(expected.header_offset()));
+        assert_that!(last.name, equal_to(expected.name));
+        assert_that!(last.size, equal_to(expected.size));
+
+        let mut file = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        let last = HeadersParser::from(&mut file).last().unwrap();
+        assert_that!(last.name.as_str(), equal_to(LONG_NAME));
+    }
+
+    #[test]
+    fn count_entries_without_headers() {
+        let path = test_resources_path().join("files_test.tar");
+        let mut file = File::open(&path).unwrap();
+
+        let count = count_entries(&mut file).unwrap();
+        assert_that!(count, equal_to(4));
+
+        let parsed = HeadersParser::from(&mut file).count();
+        assert_that!(count, equal_to(parsed));
+
+        let path = test_resources_path().join("files_append_test.tar");
+        let mut file = File::open(&path).unwrap();
+        assert_that!(count_entries(&mut file).unwrap(), equal_to(3));
+    }
+
+    #[test]
+    fn trailing_garbage_ignored() {
+        let path = test_resources_path().join("files_test.tar");
+        let mut data = std::fs::read(&path).unwrap();
+        // Some junk including valid header right after terminator
+        data.truncate(BLOCK_SIZE * 11);
+        data.extend_from_slice(&v7_header("junk.txt", 0));
+        let mut seed = 42_u32;
+        for _ in 0..BLOCK_SIZE * 3 {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            data.push((seed >> 16) as u8);
+        }
+
+        let mut source = Cursor::new(data.clone());
+        let mut parser = HeadersParser::from(&mut source)
+            .checksum_policy(ChecksumPolicy::ChecksumOnly)
+            .stop_at_terminator(true);
+        assert_that!(parser.by_ref().count(), equal_to(4));
+        assert_that!(parser.by_ref().count(), equal_to(0));
+        assert!(parser.is_clean());
+
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from(&mut source)
+            .checksum_policy(ChecksumPolicy::ChecksumOnly);
+        assert_that!(parser.by_ref().count(), equal_to(4));
+        while parser.next_any().is_some() {}
+        assert!(!parser.is_clean());
+    }
+
+    #[test]
+    fn truncated_in_the_middle_of_block() {
+        let mut data = std::fs::read(test_resources_path().join("files_test.tar")).unwrap();
+        // Fourth header is cut in half
+        data.truncate(BLOCK_SIZE * 7 + 256);
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from(&mut source);
+
+        assert_that!(parser.by_ref().count(), equal_to(3));
+        assert!(matches!(parser.check_eof(), Err(TarError::UnexpectedEof { got: 256 })));
+
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let mut parser = HeadersParser::from(&mut file);
+        assert_that!(parser.by_ref().count(), equal_to(4));
+        assert!(parser.check_eof().is_ok());
+    }
+
+    #[test]
+    fn non_zero_padding() {
+        use super::super::testutil::{archive, file};
+
+        let mut source = archive(&[file("a.txt", b"abc"), file("b.txt", b"def")]);
+        source.get_mut()[BLOCK_SIZE * 3 + 10] = b'x';
+
+        let mut parser = HeadersParser::from(&mut source);
+        assert_that!(parser.by_ref().count(), equal_to(2));
+        assert!(parser.check_padding().is_ok());
+        drop(parser);
+
+        let mut parser = HeadersParser::from(&mut source).verify_padding(true);
+        assert_that!(parser.by_ref().count(), equal_to(2));
+        match parser.check_padding() {
+            Err(TarError::NonZeroPadding { offset }) => assert_that!(offset, equal_to(BLOCK_SIZE * 3 + 10)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unpadded_data_archive() {
+        use super::super::testutil::header;
+
+        let mut data = Vec::new();
+        for (name, content) in &[("a.txt", &b"first"[..]), ("b.txt", &b"second"[..])] {
+            data.extend_from_slice(&header(name, b'0', content.len()).to_bytes());
+            data.extend_from_slice(content);
+        }
+        data.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+
+        let mut source = Cursor::new(data.clone());
+        assert_that!(HeadersParser::from(&mut source).count(), equal_to(1));
+
+        let mut source = Cursor::new(data);
+        let headers = HeadersParser::from(&mut source).unpadded_data(true).collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(2));
+        assert_that!(headers[1].name.as_str(), equal_to("b.txt"));
+        assert_that!(read_data(&mut source, &headers[1]).unwrap(), equal_to(b"second".to_vec()));
+    }
+
+    #[test]
+    fn concatenated_archives() {
+        let mut data = std::fs::read(test_resources_path().join("files_test.tar")).unwrap();
+        data.extend(std::fs::read(test_resources_path().join("files_append_test.tar")).unwrap());
+
+        let mut source = Cursor::new(data.clone());
+        assert_that!(HeadersParser::from(&mut source).count(), equal_to(4));
+
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from(&mut source).continue_past_terminator(true);
+        let names = parser.by_ref().map(|h| h.name).collect::<Vec<String>>();
+        assert_that!(names.len(), equal_to(7));
+        assert_that!(names[4].as_str(), equal_to("file.txt"));
+        assert!(parser.is_clean());
+    }
+
+    #[test]
+    fn embedded_archive() {
+        let mut data = vec![b'#'; 100];
+        data.extend(std::fs::read(test_resources_path().join("files_test.tar")).unwrap());
+
+        let mut source = Cursor::new(data.clone());
+        assert_that!(HeadersParser::from(&mut source).count(), equal_to(0));
+
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from_at(&mut source, 100);
+        let headers = parser.by_ref().collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(4));
+        assert_that!(headers[0].header_offset(), equal_to(100));
+        assert_that!(headers[1].header_offset(), equal_to(100 + BLOCK_SIZE * 2));
+        parser.trailing_zero_blocks();
+        assert_that!(parser.trailing_bytes(), equal_to(0));
+        drop(parser);
+
+        let expected = std::fs::read(test_resources_path().join("file_2.txt")).unwrap();
+        assert_that!(read_data(&mut source, &headers[1]).unwrap(), equal_to(expected.clone()));
+        assert_that!(headers[1].peek_data(&mut source, 4).unwrap(), equal_to(expected[..4].to_vec()));
+    }
+
+    #[test]
+    fn resync_after_zeroed_header() {
+        let mut data = std::fs::read(test_resources_path().join("files_test.tar")).unwrap();
+        // Header of the second entry is lost
+        let second = BLOCK_SIZE * 2;
+        data[second..second + BLOCK_SIZE].fill(0);
+
+        let mut source = Cursor::new(data.clone());
+        assert_that!(HeadersParser::from(&mut source).count(), equal_to(1));
+
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from(&mut source).resync(true);
+        let names = parser.by_ref().map(|h| h.name).collect::<Vec<String>>();
+        assert_that!(names, equal_to(vec!["file_1.txt", "file_3_dir/file_3.txt", "file_4.txt"]));
+        assert!(!parser.is_clean());
+    }
+
+    #[test]
+    fn invalid_block_callback() {
+        let mut data = std::fs::read(test_resources_path().join("files_test.tar")).unwrap();
+        let second = BLOCK_SIZE * 2;
+        data[second] ^= 0xff;
+
+        let mut seen = Vec::new();
+        let mut source = Cursor::new(data);
+        let names = HeadersParser::from(&mut source)
+            .resync(true)
+            .stop_at_terminator(true)
+            .on_invalid(|offset, h, check| seen.push((offset, h.offset, check)))
+            .map(|h| h.name)
+            .collect::<Vec<String>>();
+
+        assert_that!(names.len(), equal_to(3));
+        // Data block of the broken entry is scanned during resync too
+        let invalid = HeaderCheck::Invalid { not_ustar: false };
+        assert_that!(seen[0], equal_to((second, second, invalid)));
+        assert_that!(seen.iter().map(|s| s.0).collect::<Vec<usize>>(), equal_to(vec![second, second + BLOCK_SIZE]));
+    }
+
+    #[test]
+    fn header_offsets() {
+        let path = test_resources_path().join("files_test.tar");
+        let mut file = File::open(&path).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+
+        assert_that!(headers[0].header_offset(), equal_to(0));
+        let first_span = BLOCK_SIZE + offset_by_blocks(headers[0].size);
+        assert_that!(headers[1].header_offset(), equal_to(first_span));
+        assert_that!(headers[2].header_offset(), equal_to(first_span + BLOCK_SIZE * 2));
+    }
+
+    #[test]
+    fn until_prefix_found() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let names = HeadersParser::from(&mut file)
+            .until(|h| h.name.starts_with("file_3_dir/"))
+            .map(|h| h.name)
+            .collect::<Vec<String>>();
+
+        assert_that!(names, equal_to(vec!["file_1.txt", "file_2.txt", "file_3_dir/file_3.txt"]));
+        // Nothing after the third entry was read
+        assert_that!(file.stream_position().unwrap(), equal_to(BLOCK_SIZE as u64 * 7));
+    }
+
+    #[test]
+    fn trailing_zero_blocks_count() {
+        use super::super::testutil::file;
+
+        for &zeroes in &[0, 1, 2, 5] {
+            let mut data = [file("a.txt", b"a"), file("b.txt", b"b")].concat();
+            data.resize(data.len() + BLOCK_SIZE * zeroes, 0);
+            let mut source = Cursor::new(data);
+
+            let mut parser = HeadersParser::from(&mut source);
+            assert_that!(parser.by_ref().count(), equal_to(2));
+            assert_that!(parser.trailing_zero_blocks(), equal_to(zeroes as u8));
+        }
+
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let mut parser = HeadersParser::from(&mut file);
+        assert_that!(parser.by_ref().count(), equal_to(4));
+        // Padded up to 20 blocks
+        assert_that!(parser.trailing_zero_blocks(), equal_to(11));
+    }
+
+    #[test]
+    fn terminator_policies() {
+        use super::super::testutil::file;
+
+        let source = |zeroes: usize| {
+            let mut data = [file("a.txt", b"a"), file("b.txt", b"b")].concat();
+            data.resize(data.len() + BLOCK_SIZE * zeroes, 0);
+            Cursor::new(data)
+        };
+        let cases = [
+            (TerminatorPolicy::RequireTwo, [false, false, true]),
+            (TerminatorPolicy::AcceptOne, [false, true, true]),
+            (TerminatorPolicy::AcceptAny, [true, true, true]),
+        ];
+        for (policy, clean) in cases {
+            for (zeroes, &clean) in clean.iter().enumerate() {
+                let mut source = source(zeroes);
+                let mut parser = HeadersParser::from(&mut source).terminator_policy(policy);
+                assert_that!(parser.by_ref().count(), equal_to(2));
+                assert_that!(parser.is_clean(), equal_to(clean));
+            }
+        }
+
+        // Lone zero block ends iteration unless two blocks are required
+        let data = [file("a.txt", b"a"), vec![0; BLOCK_SIZE], file("b.txt", b"b"), vec![0; BLOCK_SIZE * 2]].concat();
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from(&mut source).terminator_policy(TerminatorPolicy::RequireTwo);
+        assert_that!(parser.by_ref().count(), equal_to(2));
+        assert!(!parser.is_clean());
+        assert_that!(parser.trailing_zero_blocks(), equal_to(2));
+        drop(parser);
+        let mut parser = HeadersParser::from(&mut source).terminator_policy(TerminatorPolicy::AcceptOne);
+        assert_that!(parser.by_ref().count(), equal_to(1));
+        assert!(parser.is_clean());
+    }
+
+    #[test]
+    fn trailing_bytes_after_terminator() {
+        let mut data = std::fs::read(test_resources_path().join("files_test.tar")).unwrap();
+        let mut source = Cursor::new(data.clone());
+        let mut parser = HeadersParser::from(&mut source);
+        assert_that!(parser.by_ref().count(), equal_to(4));
+        parser.trailing_zero_blocks();
+        assert_that!(parser.trailing_bytes(), equal_to(0));
+
+        data.extend_from_slice(b"unexpected trailing content");
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from(&mut source);
+        assert_that!(parser.by_ref().count(), equal_to(4));
+        parser.trailing_zero_blocks();
+        assert_that!(parser.trailing_bytes(), equal_to(27));
+    }
+
+    #[test]
+    fn progress_increases() {
+        use super::super::testutil::{archive, file};
+
+        let mut source = archive(&[file("a.txt", &[b'a'; 3000]), file("b.txt", &[b'b'; 100]), file("c.txt", &[b'c'; 5000])]);
+        let progress = HeadersParser::from(&mut source)
+            .with_progress()
+            .map(|(_, p)| p)
+            .collect::<Vec<Progress>>();
+
+        assert_that!(progress.len(), equal_to(3));
+        assert!(progress.windows(2).all(|w| w[0].bytes_read < w[1].bytes_read));
+        // Only terminator is left after the last entry
+        let last = progress[2];
+        assert_that!(last.total_bytes - last.bytes_read, equal_to(BLOCK_SIZE as u64 * 2));
+        assert_that!(last.percent(), greater_than(85.0));
+    }
+
+    #[test]
+    fn headers_with_data() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let mut parser = HeadersParser::from(&mut file);
+        let mut entries = Vec::new();
+        while let Some(entry) = parser.next_with_data() {
+            entries.push(entry);
+        }
+
+        assert_that!(entries.len(), equal_to(4));
+        assert!(entries.iter().all(|(h, data)| data.len() == h.size));
+        let expected = std::fs::read(test_resources_path().join("file_2.txt")).unwrap();
+        assert_that!(&entries[1].1, equal_to(&expected));
+    }
+
+    #[test]
+    fn headers_with_data_limited() {
+        use super::super::testutil::{archive, file, pax};
+
+        let mut tar = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let mut parser = HeadersParser::from(&mut tar).capture_limit(100);
+        let mut sizes = Vec::new();
+        while let Some((h, data)) = parser.next_with_data() {
+            sizes.push((h.size, data.len()));
+        }
+
+        // Entries above limit are skipped, parsing goes on
+        assert_that!(sizes, equal_to(vec![(512, 0), (15, 15), (521, 0), (16, 16)]));
+
+        // Data of PAX header before large entry is not given instead of entry data
+        let name = "d/".repeat(60);
+        let mut source = archive(&[pax(&[("path", &name)]), file("big.bin", &[1; 2000])]);
+        let mut parser = HeadersParser::from(&mut source).capture_limit(100);
+        let (h, data) = parser.next_with_data().unwrap();
+        assert_that!(h.name, equal_to(name));
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn blocking_factor() {
+        use super::super::testutil::{archive, file};
+
+        let mut tar = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        assert_that!(detect_blocking_factor(&mut tar), equal_to(20));
+
+        let mut source = archive(&[file("a.txt", b"a")]);
+        assert_that!(detect_blocking_factor(&mut source), equal_to(1));
+
+        // 4 blocks of content padded to 8
+        let mut data = archive(&[file("a.txt", b"a")]).into_inner();
+        data.resize(BLOCK_SIZE * 8, 0);
+        assert_that!(detect_blocking_factor(&mut Cursor::new(data)), equal_to(8));
+
+        // Irregular padding does not match any known factor
+        for blocks in [9, 38] {
+            let mut data = archive(&[file("a.txt", b"a")]).into_inner();
+            data.resize(BLOCK_SIZE * blocks, 0);
+            assert_that!(detect_blocking_factor(&mut Cursor::new(data)), equal_to(20));
+        }
+    }
+
+    #[test]
+    fn read_text_data() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+
+        let expected = std::fs::read_to_string(test_resources_path().join("file_2.txt")).unwrap();
+        assert_that!(read_data_string(&mut file, &headers[1]).unwrap(), equal_to(expected.clone()));
+        assert_that!(read_data_string_lossy(&mut file, &headers[1]).unwrap(), equal_to(expected));
+    }
+
+    #[test]
+    fn read_invalid_text_data() {
+        use super::super::testutil::{archive, file};
+
+        let mut source = archive(&[file("bin", &[b'o', b'k', 0xff, 0xfe])]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+
+        let err = read_data_string(&mut source, &headers[0]).unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::InvalidData));
+        assert_that!(read_data_string_lossy(&mut source, &headers[0]).unwrap(), equal_to("ok\u{fffd}\u{fffd}".to_string()));
+    }
+
+    #[test]
+    fn strict_directory_with_size() {
+        use super::super::testutil::{archive, entry, file, header};
+
+        let entries = [
+            entry(header("dir/", b'5', 3), b"abc"),
+            file("dir/file.txt", b"data"),
+        ];
+        let mut source = archive(&entries);
+        let headers = HeadersParser::from(&mut source).strict(true).collect::<Vec<Header>>();
+
+        assert_that!(headers.len(), equal_to(2));
+        assert_that!(headers[0].issues.clone(), equal_to(vec![HeaderIssue::UnexpectedSize]));
+        assert!(headers[1].issues.is_empty());
+
+        let mut source = archive(&entries);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert!(headers[0].issues.is_empty());
+    }
+
+    #[test]
+    fn strict_duplicate_names() {
+        use super::super::testutil::{archive, file};
+
+        let entries = [file("a.txt", b"1"), file("b.txt", b"2"), file("a.txt", b"3")];
+        let mut source = archive(&entries);
+        let headers = HeadersParser::from(&mut source).strict(true).collect::<Vec<Header>>();
+
+        assert!(headers[0].issues.is_empty());
+        assert_that!(headers[2].issues.clone(), equal_to(vec![HeaderIssue::DuplicateName]));
+    }
+
+    #[cfg(feature = "encoding")]
+    #[test]
+    fn shift_jis_names() {
+        use super::super::testutil::{archive, entry, header};
+
+        let (name, _, _) = encoding_rs::SHIFT_JIS.encode("日本語.txt");
+        let mut ph = header("", b'0', 0);
+        ph.set_field(HeaderProperty::Name, &name);
+        ph.repair_checksum();
+        let data = entry(ph, b"");
+
+        let mut source = archive(std::slice::from_ref(&data));
+        let headers = HeadersParser::from(&mut source)
+            .name_encoding(encoding_rs::SHIFT_JIS)
+            .collect::<Vec<Header>>();
+        assert_that!(headers[0].name.as_str(), equal_to("日本語.txt"));
+
+        let mut source = archive(&[data]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers[0].name.as_str(), not(equal_to("日本語.txt")));
+    }
+
+    #[test]
+    fn entries_filtered_by_type() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let files = entries_of_type(&mut file, HeaderType::Reg).map(|h| h.name).collect::<Vec<String>>();
+        assert_that!(files.len(), equal_to(4));
+
+        assert_that!(entries_of_type(&mut file, HeaderType::Dir).count(), equal_to(0));
+        let types = [HeaderType::Dir, HeaderType::Reg];
+        assert_that!(entries_of_types(&mut file, &types).count(), equal_to(4));
+    }
+
+    #[test]
+    fn archive_summary() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let summary = summarize(&mut file).unwrap();
+
+        assert_that!(summary.entries, equal_to(4));
+        assert_that!(summary.total_data_bytes, equal_to(512 + 15 + 521 + 16));
+        assert_that!(summary.total_archive_bytes, equal_to(BLOCK_SIZE as u64 * 20));
+        assert!(!summary.has_symlinks);
+        assert!(!summary.has_devices);
+    }
+
+    #[test]
+    fn read_all_entries() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let entries = read_all(&mut file).unwrap();
+
+        assert_that!(entries.len(), equal_to(4));
+        for (h, data) in &entries {
+            assert_that!(data.len(), equal_to(h.size));
+        }
+    }
+
+    #[test]
+    fn format_detection() {
+        use super::super::testutil::{archive, entry, file, header};
+
+        let name = "gnu_dir/".repeat(16) + "long.txt";
+        let mut source = archive(&[entry(header("././@LongLink", b'L', name.len()), name.as_bytes()), file("long.txt", b"data")]);
+        assert_that!(detect_format(&mut source), equal_to(Some(ArchiveFormat::Gnu)));
+
+        let mut source = archive(&[file("a.txt", b"data")]);
+        assert_that!(detect_format(&mut source), equal_to(Some(ArchiveFormat::Ustar)));
+
+        let mut file = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        assert_that!(detect_format(&mut file), equal_to(Some(ArchiveFormat::Pax)));
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        assert_that!(detect_format(&mut file), equal_to(Some(ArchiveFormat::Gnu)));
+
+        assert_that!(detect_format(&mut Cursor::new(vec![0; BLOCK_SIZE * 2])), equal_to(None));
+    }
+
+    const LONG_NAME: &str = "pax_dir/this_is_a_very_long_file_name_that_does_not_fit_into_one_hundred_bytes_of_ustar_name_field_at_all.txt";
+
+    #[test]
+    fn pax_metadata_consumed() {
+        let mut file = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+
+        assert_that!(headers.len(), equal_to(2));
+        assert_that!(headers[0].name.as_str(), equal_to("short.txt"));
+        assert_that!(headers[1].name.as_str(), equal_to(LONG_NAME));
+        assert_that!(headers[1].size, equal_to(18));
+        assert_that!(headers[0].pax_record("comment"), equal_to(Some(&b"ustar test"[..])));
+        assert_that!(headers[0].pax_record("path"), equal_to(None));
+        assert_that!(headers[1].pax_record("path"), equal_to(Some(LONG_NAME.as_bytes())));
+    }
+
+    #[test]
+    fn pax_records_applied_to_next_entry_only() {
+        use super::super::testutil::{archive, file, pax};
+
+        let mut source = archive(&[
+            pax(&[("path", "renamed.txt"), ("mtime", "1234.5"), ("atime", "1300.25"), ("uname", "alice")]),
+            file("original.txt", b"data"),
+            file("plain.txt", b"data"),
+        ]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+
+        assert_that!(headers[0].name.as_str(), equal_to("renamed.txt"));
+        assert_that!(headers[0].mtime, equal_to(1234));
+        assert_that!(headers[0].uname.as_str(), equal_to("alice"));
+        assert_that!(headers[0].atime, equal_to(Some(1300)));
+        assert_that!(headers[0].ctime, equal_to(None));
+        assert_that!(headers[1].atime, equal_to(None));
+        assert_that!(headers[1].name.as_str(), equal_to("plain.txt"));
+        assert!(headers[1].pax_records.is_empty());
+    }
+
+    #[test]
+    fn pax_metadata_emitted() {
+        let mut file = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).emit_metadata(true).collect::<Vec<Header>>();
+
+        let types = headers.iter().map(|h| h.typeflag).collect::<Vec<HeaderType>>();
+        assert_that!(
+            types,
+            equal_to(vec![HeaderType::Xlg, HeaderType::Reg, HeaderType::Xhd, HeaderType::Reg])
+        );
+        assert_that!(headers[3].name.as_str(), equal_to(LONG_NAME));
+    }
+
+    #[test]
+    fn pax_data_truncated() {
+        use super::super::pax::parse_records;
+        use super::super::testutil::pax;
+
+        let mut data = pax(&[("uname", "alice"), ("path", "cut_off_name.txt")]);
+        let records_len = "15 uname=alice\n25 path=cut_off_name.txt\n".len();
+        data.truncate(BLOCK_SIZE + records_len - 10);
+
+        let records = parse_records(&data[BLOCK_SIZE..]);
+        assert_that!(records, equal_to(vec![("uname".to_string(), b"alice".to_vec())]));
+
+        let mut source = Cursor::new(data);
+        let mut parser = HeadersParser::from(&mut source).emit_metadata(true);
+        let headers = parser.by_ref().collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(1));
+        assert_that!(headers[0].issues.clone(), equal_to(vec![HeaderIssue::TruncatedData]));
+    }
+
+    #[test]
+    fn pax_size_overflow() {
+        use super::super::testutil::{archive, file, pax};
+
+        let data = [pax(&[("size", "18446744073709551615")]), file("huge.bin", b"data")];
+        let mut source = archive(&data);
+        let mut parser = HeadersParser::from(&mut source);
+        assert_that!(parser.by_ref().count(), equal_to(0));
+        assert!(!parser.is_clean());
+        let mut parser = StreamParser::from(archive(&data));
+        assert_that!(parser.by_ref().count(), equal_to(0));
+        assert!(parser.error().is_some());
+
+        // Size beyond u64 is ignored
+        let mut source = archive(&[pax(&[("size", "99999999999999999999999")]), file("huge.bin", b"data")]);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(1));
+        assert_that!(headers[0].size, equal_to(4));
+    }
+
+    #[test]
+    fn gnu_long_name_consumed() {
+        let mut file = File::open(test_resources_path().join("gnu_longname_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+
+        assert_that!(headers.len(), equal_to(2));
+        assert_that!(headers[1].name.as_str(), equal_to(LONG_NAME));
+        assert_that!(count_entries(&mut file).unwrap(), equal_to(2));
+    }
+
+    #[test]
+    fn gnu_dumpdir_parsed() {
+        let mut file = File::open(test_resources_path().join("gnu_incremental_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+
+        assert_that!(headers.len(), equal_to(4));
+        assert_that!(headers[0].typeflag, equal_to(HeaderType::Dumpdir));
+        assert_that!(
+            headers[0].dumpdir.clone(),
+            equal_to(vec![('Y', "one.txt".to_string()), ('D', "sub".to_string())])
+        );
+        assert_that!(headers[1].dumpdir.clone(), equal_to(vec![('Y', "two.txt".to_string())]));
+        assert_that!(headers[2].name.as_str(), equal_to("inc_dir/one.txt"));
+        assert!(headers[2].dumpdir.is_empty());
+    }
+
+    #[test]
+    fn find_header_after_junk() {
+        let mut data = (0..100).map(|i| (i * 7 + 1) as u8).collect::<Vec<u8>>();
+        data.extend(std::fs::read(test_resources_path().join("files_test.tar")).unwrap());
+        let mut source = Cursor::new(data);
+        source.set_position(42);
+
+        let found = (0..BLOCK_SIZE as u64).find_map(|offset| try_header_at(&mut source, offset));
+        let found = found.unwrap();
+        assert_that!(found.offset, equal_to(100));
+        assert_that!(found.name.as_str(), equal_to("file_1.txt"));
+        assert_that!(source.position(), equal_to(42));
+        assert!(try_header_at(&mut source, 1 << 20).is_none());
+    }
+
+    #[test]
+    fn split_at_entry_boundaries() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let offsets = HeadersParser::from(&mut file).map(|h| h.offset).collect::<Vec<usize>>();
+
+        let points = split_points(&mut file, 2048);
+        assert_that!(points.clone(), equal_to(vec![2048, 3584]));
+        assert!(points.iter().all(|p| offsets.contains(p)));
+        // Chunk is over target only if it holds a single entry
+        let mut bounds = vec![0];
+        bounds.extend(&points);
+        for w in bounds.windows(2) {
+            let entries = offsets.iter().filter(|&&o| w[0] <= o && o < w[1]).count();
+            assert!(w[1] - w[0] <= 2048 || entries == 1);
+        }
+
+        // PAX headers stay with their entries
+        let mut file = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).emit_metadata(true).collect::<Vec<Header>>();
+        assert_that!(headers[2].typeflag, equal_to(HeaderType::Xhd));
+        assert_that!(split_points(&mut file, 1), equal_to(vec![headers[2].offset]));
+    }
+
+    #[test]
+    fn lenient_size_field() {
+        let mut ph = PosixHeader::from(0, v7_header("junk_size.txt", 0));
+        ph.set_field(HeaderProperty::Size, b"17 garbage");
+        ph.repair_checksum();
+        assert_that!(ph.numeric_field(HeaderProperty::Size), equal_to(Err(FieldError::Malformed)));
+        assert_that!(ph.numeric_field_lenient(HeaderProperty::Size), equal_to(Ok(0o17)));
+
+        let mut data = ph.to_bytes().to_vec();
+        data.extend_from_slice(&[b'x'; BLOCK_SIZE]);
+        data.extend_from_slice(&v7_header("next.txt", 0));
+        let policy = ChecksumPolicy::ChecksumOnly;
+
+        let mut source = Cursor::new(data);
+        let strict = HeadersParser::from(&mut source).checksum_policy(policy).collect::<Vec<Header>>();
+        assert_that!(strict[0].size, equal_to(0));
+        assert_that!(strict.len(), equal_to(1));
+
+        let lenient = HeadersParser::from(&mut source)
+            .checksum_policy(policy)
+            .lenient_numbers(true)
+            .collect::<Vec<Header>>();
+        assert_that!(lenient[0].size, equal_to(15));
+        assert_that!(lenient[0].size_checked(), equal_to(Ok(15)));
+        assert_that!(strict[0].size_checked(), equal_to(Err(FieldError::Malformed)));
+        assert_that!(lenient[1].name.as_str(), equal_to("next.txt"));
+    }
+
+    #[test]
+    fn data_range() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let headers = HeadersParser::from(&mut file).collect::<Vec<Header>>();
+        let full = read_data(&mut file, &headers[2]).unwrap();
+
+        assert_that!(read_data_range(&mut file, &headers[2], 10, 10).unwrap(), equal_to(full[10..20].to_vec()));
+        assert_that!(read_data_range(&mut file, &headers[2], 515, 100).unwrap(), equal_to(full[515..].to_vec()));
+        assert!(read_data_range(&mut file, &headers[2], 521, 1).unwrap().is_empty());
+        let err = read_data_range(&mut file, &headers[2], 522, 1).unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::InvalidInput));
+    }
+
+    #[test]
+    fn entry_size_limit() {
+        let mut block = v7_header("huge.bin", 0);
+        block[HeaderProperty::Size].copy_from_slice(b"40000000000\0");
+        let h = Header::from(PosixHeader::from(0, block));
+        assert_that!(h.size as u64, equal_to(4 * 1024 * 1024 * 1024));
+
+        // Source has no data at all, so only the limit check could fail that fast
+        let mut source = Cursor::new(block.to_vec());
+        let gib = 1024 * 1024 * 1024;
+        let err = read_data_limited(&mut source, &h, gib).unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::InvalidData));
+        let err = entry_reader_limited(&mut source, &h, gib).unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::InvalidData));
+        let err = read_data(&mut source, &h).unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::InvalidData));
+        assert!(entry_reader(&mut source, &h).is_ok());
+    }
+
+    #[test]
+    fn huge_size_field() {
+        use super::super::testutil::{archive, entry, file, header};
+
+        let mut overflow = [0xff; 12];
+        overflow[0] = 0x80;
+        let mut max_u64 = [0xff; 12];
+        max_u64[..4].copy_from_slice(&[0x80, 0, 0, 0]);
+        for size in [overflow, max_u64] {
+            let mut ph = header("huge.bin", b'0', 0);
+            ph.set_field(HeaderProperty::Size, &size);
+            ph.repair_checksum();
+            let data = [entry(ph, b""), file("next.txt", b"next")];
+
+            let mut source = archive(&data);
+            let mut parser = HeadersParser::from(&mut source);
+            assert_that!(parser.by_ref().count(), equal_to(0));
+            assert!(!parser.is_clean());
+            drop(parser);
+            let names = HeadersParser::from(&mut source).resync(true).map(|h| h.name).collect::<Vec<String>>();
+            assert_that!(names, equal_to(vec!["next.txt".to_string()]));
+            assert!(HeadersParser::from(&mut source).last().is_none());
+            assert_that!(count_entries(&mut source).unwrap(), equal_to(0));
+            assert_that!(TarMeta::from_reader(&mut source).unwrap().len(), equal_to(2));
+
+            let mut parser = StreamParser::from(archive(&data));
+            assert_that!(parser.by_ref().count(), equal_to(0));
+            assert!(parser.error().is_some());
+        }
+    }
+
+    #[test]
+    fn oversized_metadata_not_allocated() {
+        use super::super::testutil::{archive, entry, header};
+
+        // About 8 GiB declared with no data behind
+        let huge = |typeflag: u8| {
+            let mut ph = header("huge", typeflag, 0);
+            ph.set_field(HeaderProperty::Size, b"77777777777\0");
+            ph.repair_checksum();
+            entry(ph, b"")
+        };
+
+        // Sizes beyond source are not accepted at all
+        for typeflag in [b'D', b'x', b'L'] {
+            let mut source = archive(&[huge(typeflag)]);
+            let mut parser = HeadersParser::from(&mut source).emit_metadata(true);
+            assert_that!(parser.by_ref().count(), equal_to(0));
+            assert!(!parser.is_clean());
+        }
+
+        for typeflag in [b'D', b'x'] {
+            let mut parser = StreamParser::from(archive(&[huge(typeflag)]));
+            assert_that!(parser.by_ref().count(), equal_to(0));
+            assert!(parser.error().is_some());
+        }
+    }
+
+    #[test]
+    fn empty_archives() {
+        for len in [0, BLOCK_SIZE * 2] {
+            let mut source = Cursor::new(vec![0; len]);
+            let mut parser = HeadersParser::from(&mut source);
+            assert_that!(parser.by_ref().count(), equal_to(0));
+            assert!(parser.is_clean());
+            assert!(parser.check_eof().is_ok());
+            assert_that!(parser.trailing_zero_blocks() as usize, equal_to(len / BLOCK_SIZE));
+            assert_that!(parser.trailing_bytes(), equal_to(0));
+            drop(parser);
+
+            assert_that!(count_entries(&mut source).unwrap(), equal_to(0));
+            assert_that!(HeadersParser::from(&mut source).last().is_none(), is(true));
+            assert_that!(TarMeta::from_reader(&mut source).unwrap().iter().count(), equal_to(0));
+
+            let mut stream = StreamParser::from(Cursor::new(vec![0; len]));
+            assert_that!(stream.by_ref().count(), equal_to(0));
+            assert!(stream.error().is_none());
+        }
+    }
+
+    #[test]
+    fn header_after_dumpdir() {
+        let mut file = File::open(test_resources_path().join("gnu_incremental_test.tar")).unwrap();
+        let mut parser = HeadersParser::from(&mut file).strict(true).stop_at_terminator(true);
+        let headers = parser.by_ref().collect::<Vec<Header>>();
+        assert!(parser.is_clean());
+        drop(parser);
+
+        // Listing payload takes one block and is never taken for a header
+        let offsets = headers.iter().map(|h| h.header_offset()).collect::<Vec<usize>>();
+        assert_that!(offsets, equal_to(vec![0, BLOCK_SIZE * 2, BLOCK_SIZE * 4, BLOCK_SIZE * 6]));
+        assert!(headers.iter().all(|h| h.check == HeaderCheck::Valid && h.issues.is_empty()));
+        assert_that!(headers[1].name.as_str(), equal_to("inc_dir/sub/"));
+        assert_that!(headers[1].typeflag, equal_to(HeaderType::Dumpdir));
+        assert_that!(read_data_string(&mut file, &headers[2]).unwrap(), equal_to("one\n".to_string()));
+
+        let last = HeadersParser::from(&mut file).last().unwrap();
+        assert_that!(last.name.as_str(), equal_to("inc_dir/sub/two.txt"));
+    }
+
+    /// Build old v7 header (no magic) with correct checksum.
+    fn v7_header(name: &str, size: usize) -> [u8; BLOCK_SIZE] {
+        let mut block = [0; BLOCK_SIZE];
+        block[HeaderProperty::Name][..name.len()].copy_from_slice(name.as_bytes());
+        block[HeaderProperty::Mode].copy_from_slice(b"0000644\0");
+        block[HeaderProperty::Size].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        block[HeaderProperty::Mtime].copy_from_slice(b"13615525701\0");
+        block[HeaderProperty::Typeflag][0] = b'0';
+        block[HeaderProperty::Chksum].copy_from_slice(b"        ");
+        let sum: usize = block.iter().map(|&b| b as usize).sum();
+        block[HeaderProperty::Chksum].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        block
+    }
+
+    fn v7_archive() -> Cursor<Vec<u8>> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&v7_header("v7_1.txt", 3));
+        data.extend_from_slice(&[b'a'; BLOCK_SIZE]);
+        data.extend_from_slice(&v7_header("v7_2.txt", 0));
+        data.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+        Cursor::new(data)
+    }
+
+    #[test]
+    fn checksum_policy_v7_archive() {
+        let mut source = v7_archive();
+        let strict = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(strict.len(), equal_to(0));
+
+        let mut source = v7_archive();
+        let headers = HeadersParser::from(&mut source)
+            .checksum_policy(ChecksumPolicy::ChecksumOnly)
+            .collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(2));
+        assert_that!(headers[0].check, equal_to(HeaderCheck::Invalid { not_ustar: true }));
+        assert_that!(headers[0].size, equal_to(3));
+    }
+
+    #[test]
+    fn checksum_policy_none_accepts_broken_checksum() {
+        let mut block = v7_header("broken.txt", 0);
+        block[HeaderProperty::Chksum].copy_from_slice(b"0000001\0");
+        let mut data = block.to_vec();
+        data.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+
+        let mut source = Cursor::new(data.clone());
+        let checksum_only = HeadersParser::from(&mut source)
+            .checksum_policy(ChecksumPolicy::ChecksumOnly)
+            .count();
+        assert_that!(checksum_only, equal_to(0));
+
+        let mut source = Cursor::new(data);
+        let headers = HeadersParser::from(&mut source)
+            .checksum_policy(ChecksumPolicy::None)
+            .collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(1));
+        assert_that!(headers[0].check, equal_to(HeaderCheck::Invalid { not_ustar: false }));
+    }
 }
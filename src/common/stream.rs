@@ -0,0 +1,317 @@
+use std::io::{self, Read, Seek};
+
+use crate::common::meta::PosixHeader;
+
+use super::{SkipData, BLOCK_SIZE};
+use super::meta::{FieldError, Header, HeaderCheck, HeaderProperty, HeaderType};
+use super::offset_by_blocks;
+use super::pax::{parse_dumpdir, PendingMeta};
+use super::read::{read_block, read_to_vec, HeadersParser, MAX_METADATA_SIZE};
+
+/// Reader that fails once more than `limit` bytes are requested from source,
+/// protects from hostile or endless sources.
+#[derive(Debug)]
+pub struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, limit: u64) -> LimitedReader<R> {
+        LimitedReader { inner, remaining: limit }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            // Source that ends right at the limit is fine
+            return match self.inner.read(&mut [0; 1])? {
+                0 => Ok(0),
+                _ => Err(io::Error::other("read limit exceeded")),
+            };
+        }
+        let max = buf.len().min(self.remaining as usize);
+        let read = self.inner.read(&mut buf[..max])?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Extracts tar Headers from source that can't seek (pipe, decompressor, socket).
+/// Entries data is read and discarded.
+#[derive(Debug)]
+pub struct StreamParser<R> {
+    offset: usize,
+    source: R,
+    pending: PendingMeta,
+    done: bool,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> StreamParser<R> {
+    pub fn from(source: R) -> StreamParser<R> {
+        StreamParser {
+            offset: 0,
+            source,
+            pending: PendingMeta::default(),
+            done: false,
+            error: None,
+        }
+    }
+
+    /// Error that stopped iteration (None if archive ended properly).
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    /// Give back underlying source.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+
+    /// Read next valid header, metadata pseudo entries are consumed silently.
+    fn next_header(&mut self) -> io::Result<Option<Header>> {
+        loop {
+            let mut buffer = [0; BLOCK_SIZE];
+            match read_block(&mut self.source, &mut buffer)? {
+                // Source ended right at block boundary (e.g. empty one)
+                0 => return Ok(None),
+                BLOCK_SIZE => {}
+                _ => return Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+            let ph = PosixHeader::from(self.offset, buffer);
+            self.offset += BLOCK_SIZE;
+            if ph.check() != HeaderCheck::Valid {
+                return Ok(None);
+            }
+
+            if ph.numeric_field(HeaderProperty::Size) == Err(FieldError::Overflow) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "entry size does not fit"));
+            }
+            let mut h = Header::from(ph);
+            let mut consumed = 0;
+            let metadata = h.typeflag.is_metadata();
+            let in_memory = h.size as u64 <= MAX_METADATA_SIZE;
+            if metadata {
+                if in_memory {
+                    let data = read_to_vec(&mut self.source, h.size)?;
+                    consumed = h.size;
+                    self.pending.consume(h.typeflag, &data);
+                }
+            } else {
+                self.pending.apply(&mut h);
+                if h.typeflag == HeaderType::Dumpdir && in_memory {
+                    let data = read_to_vec(&mut self.source, h.size)?;
+                    consumed = h.size;
+                    h.dumpdir = parse_dumpdir(&data);
+                }
+            }
+            let shift = offset_by_blocks(h.size);
+            self.offset = self
+                .offset
+                .checked_add(shift)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "entry size does not fit"))?;
+            self.skip_data((shift - consumed) as u64)?;
+            if !metadata {
+                return Ok(Some(h));
+            }
+        }
+    }
+}
+
+/// Parsing of untrusted sources with bounded amount of read data.
+impl<R: Read> StreamParser<LimitedReader<R>> {
+    /// Parser that reads at most `limit` bytes from source, reading more stops iteration with error.
+    pub fn with_limit(source: R, limit: u64) -> StreamParser<LimitedReader<R>> {
+        StreamParser::from(LimitedReader::new(source, limit))
+    }
+}
+
+/// Source could not seek, so skipped data is read in chunks and discarded.
+impl<R: Read> SkipData for StreamParser<R> {
+    fn skip_data(&mut self, n: u64) -> io::Result<()> {
+        if io::copy(&mut self.source.by_ref().take(n), &mut io::sink())? != n {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for StreamParser<R> {
+    type Item = Header;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let h = match self.next_header() {
+            Ok(h) => h,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        };
+        self.done = h.is_none();
+        h
+    }
+}
+
+/// Parser selected by `open_auto` depending on source content.
+#[derive(Debug)]
+pub enum AutoParser<'a, S> {
+    Plain(HeadersParser<'a, S>),
+    #[cfg(feature = "gzip")]
+    Gzip(StreamParser<flate2::read::GzDecoder<&'a mut S>>),
+}
+
+impl<'a, S: Read + Seek> Iterator for AutoParser<'a, S> {
+    type Item = Header;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            AutoParser::Plain(p) => p.next(),
+            #[cfg(feature = "gzip")]
+            AutoParser::Gzip(p) => p.next(),
+        }
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Open plain or gzip compressed tar by checking first bytes of source.
+/// Compressed sources are parsed with `StreamParser` (offsets are related to decompressed data).
+/// Without `gzip` feature compressed source gives `InvalidData` error.
+pub fn open_auto<S: Read + Seek>(source: &mut S) -> io::Result<AutoParser<'_, S>> {
+    source.rewind()?;
+    let mut magic = [0; 2];
+    let read = source.read(&mut magic)?;
+    source.rewind()?;
+
+    if read == 2 && magic == GZIP_MAGIC {
+        #[cfg(feature = "gzip")]
+        return Ok(AutoParser::Gzip(StreamParser::from(flate2::read::GzDecoder::new(source))));
+        #[cfg(not(feature = "gzip"))]
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "gzip support is not enabled"));
+    }
+    Ok(AutoParser::Plain(HeadersParser::from(source)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use hamcrest2::prelude::*;
+
+    use super::*;
+    use super::super::testutil::test_resources_path;
+
+    fn listing<I: Iterator<Item = Header>>(headers: I) -> Vec<(String, usize, usize)> {
+        headers.map(|h| (h.name.clone(), h.size, h.header_offset())).collect()
+    }
+
+    #[test]
+    fn stream_parser_same_as_seeking() {
+        let mut file = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        let expected = listing(HeadersParser::from(&mut file));
+
+        let file = File::open(test_resources_path().join("pax_test.tar")).unwrap();
+        assert_that!(listing(StreamParser::from(file)), equal_to(expected));
+    }
+
+    /// Reader that hides Seek of underlying source.
+    struct ReadOnly<R>(R);
+
+    impl<R: Read> Read for ReadOnly<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    /// Source counting bytes actually read from it.
+    struct Counting<R> {
+        inner: R,
+        read: u64,
+    }
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for Counting<R> {
+        fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn skip_seek_vs_read() {
+        use std::io::Cursor;
+
+        use super::super::testutil::{archive, file};
+
+        let data = vec![b'x'; 64 * 1024];
+        let entries = (0..8).map(|i| file(&format!("big_{}.bin", i), &data)).collect::<Vec<Vec<u8>>>();
+        let bytes = archive(&entries).into_inner();
+        let len = bytes.len() as u64;
+
+        // Seeking parser reads headers and the first terminator block only
+        let mut source = Counting { inner: Cursor::new(bytes.clone()), read: 0 };
+        assert_that!(HeadersParser::from(&mut source).count(), equal_to(8));
+        assert_that!(source.read, equal_to(BLOCK_SIZE as u64 * 9));
+
+        let mut parser = StreamParser::from(Counting { inner: ReadOnly(Cursor::new(bytes)), read: 0 });
+        assert_that!(parser.by_ref().count(), equal_to(8));
+        assert_that!(parser.into_inner().read, equal_to(len - BLOCK_SIZE as u64));
+    }
+
+    #[test]
+    fn read_limit_exceeded() {
+        let file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        // Enough for the first two entries only
+        let mut parser = StreamParser::with_limit(file, BLOCK_SIZE as u64 * 5);
+        assert_that!(parser.by_ref().count(), equal_to(2));
+        assert_that!(parser.error().map(|e| e.to_string()), equal_to(Some("read limit exceeded".to_string())));
+
+        let file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let len = file.metadata().unwrap().len();
+        let mut parser = StreamParser::with_limit(file, len);
+        assert_that!(parser.by_ref().count(), equal_to(4));
+        assert!(parser.error().is_none());
+    }
+
+    #[test]
+    fn open_auto_plain() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let parser = open_auto(&mut file).unwrap();
+        assert!(matches!(parser, AutoParser::Plain(_)));
+        assert_that!(parser.count(), equal_to(4));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn open_auto_gzip() {
+        let mut file = File::open(test_resources_path().join("files_test.tar")).unwrap();
+        let expected = listing(open_auto(&mut file).unwrap());
+
+        let mut gz = File::open(test_resources_path().join("files_test.tar.gz")).unwrap();
+        let parser = open_auto(&mut gz).unwrap();
+        assert!(matches!(parser, AutoParser::Gzip(_)));
+        assert_that!(listing(parser), equal_to(expected));
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn open_auto_gzip_disabled() {
+        let mut gz = File::open(test_resources_path().join("files_test.tar.gz")).unwrap();
+        let err = open_auto(&mut gz).unwrap_err();
+        assert_that!(err.kind(), equal_to(io::ErrorKind::InvalidData));
+    }
+}
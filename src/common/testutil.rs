@@ -0,0 +1,68 @@
+//! Helpers to craft archives for tests.
+use std::env;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use super::meta::{HeaderProperty, PosixHeader};
+use super::pax::format_record;
+use super::{offset_by_blocks, BLOCK_SIZE};
+
+pub fn test_resources_path() -> PathBuf {
+    let basedir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    Path::new(&basedir).join("test")
+}
+
+/// Valid POSIX ustar header, fields could be changed later via `set_field` and `repair_checksum`.
+pub fn header(name: &str, typeflag: u8, size: usize) -> PosixHeader {
+    let mut ph = PosixHeader::from(0, [0; BLOCK_SIZE]);
+    ph.set_field(HeaderProperty::Name, name.as_bytes());
+    ph.set_field(HeaderProperty::Mode, b"0000644");
+    ph.set_field(HeaderProperty::Uid, b"0001750");
+    ph.set_field(HeaderProperty::Gid, b"0001750");
+    ph.set_field(HeaderProperty::Size, format!("{:011o}", size).as_bytes());
+    ph.set_field(HeaderProperty::Mtime, b"13615525701");
+    ph.set_field(HeaderProperty::Typeflag, &[typeflag]);
+    ph.set_field(HeaderProperty::Magic, b"ustar\0");
+    ph.set_field(HeaderProperty::Version, b"00");
+    ph.set_field(HeaderProperty::Uname, b"rust");
+    ph.set_field(HeaderProperty::Gname, b"grust");
+    ph.repair_checksum();
+    ph
+}
+
+/// Link header (hard or symbolic depending on typeflag) without data.
+pub fn link(name: &str, typeflag: u8, target: &str) -> PosixHeader {
+    let mut ph = header(name, typeflag, 0);
+    ph.set_field(HeaderProperty::Linkname, target.as_bytes());
+    ph.repair_checksum();
+    ph
+}
+
+/// Header block followed by data padded to blocks.
+pub fn entry(header: PosixHeader, data: &[u8]) -> Vec<u8> {
+    let mut bytes = header.to_bytes().to_vec();
+    bytes.extend_from_slice(data);
+    bytes.resize(BLOCK_SIZE + offset_by_blocks(data.len()), 0);
+    bytes
+}
+
+/// Regular file entry.
+pub fn file(name: &str, data: &[u8]) -> Vec<u8> {
+    entry(header(name, b'0', data.len()), data)
+}
+
+/// PAX extended header entry (typeflag x) with records.
+pub fn pax(records: &[(&str, &str)]) -> Vec<u8> {
+    let data = records
+        .iter()
+        .flat_map(|(k, v)| format_record(k, v.as_bytes()))
+        .collect::<Vec<u8>>();
+    entry(header("PaxHeaders/entry", b'x', data.len()), &data)
+}
+
+/// Archive from entries with two zero blocks at the end.
+pub fn archive(entries: &[Vec<u8>]) -> Cursor<Vec<u8>> {
+    let mut data = entries.concat();
+    data.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+    Cursor::new(data)
+}
@@ -0,0 +1,1063 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Seek, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+use super::meta::{ChecksumPolicy, Header, HeaderProperty, HeaderType, PosixHeader};
+use super::read::{entry_reader, read_data, HeadersParser};
+use super::pax::{format_record, PaxRecord};
+use super::{offset_by_blocks, BLOCK_SIZE};
+
+const MAGIC: &[u8; 6] = b"ustar\0";
+const VERSION: &[u8; 2] = b"00";
+/// Name of PAX extended headers written for long names.
+const PAX_HEADER_NAME: &str = "././@PaxHeader";
+
+fn invalid_input(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+/// Names with NUL would be silently cut when read back and newline breaks PAX records.
+fn check_name(name: &str) -> io::Result<()> {
+    if name.contains(['\0', '\n']) {
+        return Err(invalid_input("name contains NUL or newline character"));
+    }
+    Ok(())
+}
+
+/// Split long name into ustar prefix and name fields.
+/// Returns None if name could not be split to fit into both fields.
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    let name_len = HeaderProperty::Name.len();
+    let prefix_len = HeaderProperty::Prefix.len();
+    if name.len() <= name_len {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| i)
+        .find(|&i| i > 0 && i <= prefix_len && name.len() - i - 1 <= name_len && i + 1 < name.len())
+        .map(|i| (&name[..i], &name[i + 1..]))
+}
+
+/// Cut string to fit into header field without breaking UTF-8 chars.
+fn truncate(value: &str, len: usize) -> &str {
+    let mut end = value.len().min(len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// PAX records for values that don't fit into ustar header fields.
+fn long_names_records(name: &str, linkname: &str) -> Vec<u8> {
+    let mut records = Vec::new();
+    if split_name(name).is_none() {
+        records.extend(format_record("path", name.as_bytes()));
+    }
+    if linkname.len() > HeaderProperty::Linkname.len() {
+        records.extend(format_record("linkpath", linkname.as_bytes()));
+    }
+    records
+}
+
+/// Number could be written as octal string into header field.
+fn fits_octal(value: u128, range: Range<usize>) -> bool {
+    value < 1 << (3 * (range.len() - 1))
+}
+
+/// Write number as zero filled octal string followed by NUL.
+fn set_octal(ph: &mut PosixHeader, range: Range<usize>, value: u128) -> io::Result<()> {
+    let digits = range.len() - 1;
+    let octal = format!("{:0width$o}", value, width = digits);
+    if octal.len() > digits {
+        return Err(invalid_input("numeric value does not fit into header field"));
+    }
+    ph.set_field(range, octal.as_bytes());
+    Ok(())
+}
+
+impl Header {
+    /// Builder with all fields of this header, e.g. to change one field and write header again.
+    pub fn to_builder(&self) -> HeaderBuilder {
+        let mut builder = HeaderBuilder::new(&self.name, self.typeflag)
+            .linkname(&self.linkname)
+            .mode(self.mode)
+            .mtime(self.mtime)
+            .size(self.size)
+            .uid(self.uid)
+            .gid(self.gid)
+            .uname(&self.uname)
+            .gname(&self.gname);
+        if let Some((major, minor)) = self.device() {
+            builder = builder.device(major, minor);
+        }
+        // Path records are written again from names, size one could be outdated by data changes
+        for (key, value) in &self.pax_records {
+            if !matches!(key.as_str(), "path" | "linkpath" | "size") {
+                builder = builder.pax_record(key, value);
+            }
+        }
+        builder
+    }
+}
+
+/// Builds valid POSIX ustar header block.
+#[derive(Debug, Clone)]
+pub struct HeaderBuilder {
+    name: String,
+    typeflag: HeaderType,
+    linkname: String,
+    mode: u64,
+    mtime: u128,
+    size: usize,
+    uid: u64,
+    gid: u64,
+    uname: String,
+    gname: String,
+    device: Option<(u32, u32)>,
+    records: Vec<PaxRecord>,
+}
+
+impl HeaderBuilder {
+    pub fn new(name: &str, typeflag: HeaderType) -> HeaderBuilder {
+        HeaderBuilder {
+            name: name.to_string(),
+            typeflag,
+            linkname: String::new(),
+            mode: 0o644,
+            mtime: 0,
+            size: 0,
+            uid: 0,
+            gid: 0,
+            uname: String::new(),
+            gname: String::new(),
+            device: None,
+            records: Vec::new(),
+        }
+    }
+
+    pub fn mode(mut self, mode: u64) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Modification time in seconds since UNIX epoch.
+    pub fn mtime(mut self, mtime: u128) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// Size of data that follows header.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Link target for hard and symbolic links.
+    pub fn linkname(mut self, linkname: &str) -> Self {
+        self.linkname = linkname.to_string();
+        self
+    }
+
+    pub fn uid(mut self, uid: u64) -> Self {
+        self.uid = uid;
+        self
+    }
+
+    pub fn gid(mut self, gid: u64) -> Self {
+        self.gid = gid;
+        self
+    }
+
+    /// Owner user name, up to 32 bytes.
+    pub fn uname(mut self, uname: &str) -> Self {
+        self.uname = uname.to_string();
+        self
+    }
+
+    /// Owner group name, up to 32 bytes.
+    pub fn gname(mut self, gname: &str) -> Self {
+        self.gname = gname.to_string();
+        self
+    }
+
+    /// Major and minor numbers for character and block devices.
+    pub fn device(mut self, major: u32, minor: u32) -> Self {
+        self.device = Some((major, minor));
+        self
+    }
+
+    /// PAX record (e.g. xattr or sub-second time) written by `TarWriter` before the entry, ignored by `build`.
+    pub fn pax_record(mut self, key: &str, value: &[u8]) -> Self {
+        self.records.push((key.to_string(), value.to_vec()));
+        self
+    }
+
+    /// PAX records for numbers that don't fit into header fields, such fields are zeroed.
+    /// Records set by `pax_record` are kept as they are.
+    fn overflow_records(&mut self) -> Vec<u8> {
+        let mut overflow = Vec::new();
+        if !fits_octal(self.uid as u128, HeaderProperty::Uid) {
+            overflow.push(("uid", self.uid.to_string()));
+            self.uid = 0;
+        }
+        if !fits_octal(self.gid as u128, HeaderProperty::Gid) {
+            overflow.push(("gid", self.gid.to_string()));
+            self.gid = 0;
+        }
+        if !fits_octal(self.mtime, HeaderProperty::Mtime) {
+            overflow.push(("mtime", self.mtime.to_string()));
+            self.mtime = 0;
+        }
+        if !fits_octal(self.size as u128, HeaderProperty::Size) {
+            overflow.push(("size", self.size.to_string()));
+            self.size = 0;
+        }
+        overflow
+            .iter()
+            .filter(|(key, _)| !self.records.iter().any(|(k, _)| k == key))
+            .flat_map(|(key, value)| format_record(key, value.as_bytes()))
+            .collect()
+    }
+
+    /// Create header block with calculated checksum.
+    /// Names with NUL or newline characters are rejected.
+    /// Names longer than 100 bytes are split into prefix and name fields if possible.
+    pub fn build(&self) -> io::Result<PosixHeader> {
+        check_name(&self.name)?;
+        check_name(&self.linkname)?;
+        let (prefix, name) = split_name(&self.name).ok_or_else(|| invalid_input("name is too long"))?;
+        if self.linkname.len() > HeaderProperty::Linkname.len() {
+            return Err(invalid_input("link name is too long"));
+        }
+        if self.uname.len() > HeaderProperty::Uname.len() || self.gname.len() > HeaderProperty::Gname.len() {
+            return Err(invalid_input("user or group name is too long"));
+        }
+        let flag = self
+            .typeflag
+            .flag()
+            .ok_or_else(|| invalid_input("header type could not be written"))?;
+
+        let mut ph = PosixHeader::from(0, [0; BLOCK_SIZE]);
+        ph.set_field(HeaderProperty::Name, name.as_bytes());
+        ph.set_field(HeaderProperty::Prefix, prefix.as_bytes());
+        set_octal(&mut ph, HeaderProperty::Mode, self.mode as u128)?;
+        set_octal(&mut ph, HeaderProperty::Uid, self.uid as u128)?;
+        set_octal(&mut ph, HeaderProperty::Gid, self.gid as u128)?;
+        set_octal(&mut ph, HeaderProperty::Size, self.size as u128)?;
+        set_octal(&mut ph, HeaderProperty::Mtime, self.mtime)?;
+        ph.set_field(HeaderProperty::Typeflag, &[flag]);
+        ph.set_field(HeaderProperty::Linkname, self.linkname.as_bytes());
+        ph.set_field(HeaderProperty::Magic, MAGIC);
+        ph.set_field(HeaderProperty::Version, VERSION);
+        ph.set_field(HeaderProperty::Uname, self.uname.as_bytes());
+        ph.set_field(HeaderProperty::Gname, self.gname.as_bytes());
+        if let Some((major, minor)) = self.device {
+            set_octal(&mut ph, HeaderProperty::Devmajor, major as u128)?;
+            set_octal(&mut ph, HeaderProperty::Devminor, minor as u128)?;
+        }
+        ph.repair_checksum();
+        Ok(ph)
+    }
+}
+
+/// Writes tar archive entries to destination.
+/// Archive is complete only after `finish`, dropped writer leaves archive without terminator
+/// (`HeadersParser::trailing_zero_blocks` gives 0 for it, which means truncated).
+#[derive(Debug)]
+pub struct TarWriter<W> {
+    dst: W,
+    names: HashSet<String>,
+    strict_links: bool,
+    unique_names: bool,
+    follow_symlinks: bool,
+    stream_buffer: StreamBuffer,
+    blocking_factor: usize,
+    written: u64,
+    missing_link_targets: Vec<String>,
+}
+
+/// Where `TarWriter::append_stream` keeps data until its size is known.
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+pub enum StreamBuffer {
+    /// Fast, but the whole entry data is held in memory.
+    #[default]
+    Memory,
+    /// Temporary file in `std::env::temp_dir`, suitable for large data but written to disk twice.
+    TempFile,
+}
+
+impl TarWriter<()> {
+    /// Exact archive size for entries given as (name, data length) pairs.
+    /// Includes headers, data padding, PAX headers for long names and terminator.
+    pub fn predict_size(entries: &[(&str, usize)]) -> u64 {
+        let entries_size: usize = entries
+            .iter()
+            .map(|(name, len)| {
+                let records = long_names_records(name, "").len();
+                let pax = if records > 0 { BLOCK_SIZE + offset_by_blocks(records) } else { 0 };
+                pax + BLOCK_SIZE + offset_by_blocks(*len)
+            })
+            .sum();
+        (entries_size + BLOCK_SIZE * 2) as u64
+    }
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(dst: W) -> TarWriter<W> {
+        TarWriter {
+            dst,
+            names: HashSet::new(),
+            strict_links: false,
+            unique_names: false,
+            follow_symlinks: false,
+            stream_buffer: StreamBuffer::default(),
+            blocking_factor: 1,
+            written: 0,
+            missing_link_targets: Vec::new(),
+        }
+    }
+
+    /// Fail on hard links to entries that were not appended before.
+    /// By default such links are written and their targets are listed by `missing_link_targets`.
+    pub fn strict_links(mut self, strict: bool) -> Self {
+        self.strict_links = strict;
+        self
+    }
+
+    /// Fail with `AlreadyExists` error on entry with name that was already appended.
+    pub fn unique_names(mut self, unique: bool) -> Self {
+        self.unique_names = unique;
+        self
+    }
+
+    /// Store symlink targets instead of links when appending directory tree (default is to keep links).
+    /// Links to directories already packed (loops or several links to one directory) and dangling links
+    /// are stored as links.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// Archive size written by `finish` is padded to multiple of `factor` blocks
+    /// (GNU tar uses 20 by default), 1 means no padding after terminator.
+    pub fn blocking_factor(mut self, factor: usize) -> Self {
+        self.blocking_factor = factor.max(1);
+        self
+    }
+
+    /// Buffering used for data of unknown length by `append_stream` (memory by default).
+    pub fn stream_buffer(mut self, buffer: StreamBuffer) -> Self {
+        self.stream_buffer = buffer;
+        self
+    }
+
+    fn check_unique(&self, name: &str) -> io::Result<()> {
+        if self.unique_names && self.names.contains(name) {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("duplicate entry name: {}", name)));
+        }
+        Ok(())
+    }
+
+    /// Hard link targets that were not appended before the link (non strict mode only).
+    pub fn missing_link_targets(&self) -> &[String] {
+        &self.missing_link_targets
+    }
+
+    /// Write header block followed by data padded to blocks.
+    fn write_entry(&mut self, header: &PosixHeader, data: &[u8]) -> io::Result<()> {
+        if header.size() != data.len() {
+            return Err(invalid_input("data length differs from header size"));
+        }
+        self.dst.write_all(&header.to_bytes())?;
+        self.dst.write_all(data)?;
+        let padding = offset_by_blocks(data.len()) - data.len();
+        self.dst.write_all(&[0; BLOCK_SIZE][..padding])?;
+        self.written += (BLOCK_SIZE + data.len() + padding) as u64;
+        Ok(())
+    }
+
+    /// Write header block followed by data padded to blocks.
+    /// Data length must be the same as header size.
+    pub fn append(&mut self, header: &PosixHeader, data: &[u8]) -> io::Result<()> {
+        self.check_unique(&header.name())?;
+        self.write_entry(header, data)?;
+        self.names.insert(header.name());
+        Ok(())
+    }
+
+    /// Build and write header, names that don't fit into ustar header are written to PAX extended header.
+    pub fn append_builder(&mut self, builder: HeaderBuilder, data: &[u8]) -> io::Result<()> {
+        if builder.size != data.len() {
+            return Err(invalid_input("data length differs from header size"));
+        }
+        self.append_reader(builder, data)
+    }
+
+    /// Append entry with data of unknown length, size of builder is ignored.
+    /// Data is buffered first (see `stream_buffer`) because size is written before data.
+    pub fn append_stream<R: Read>(&mut self, builder: HeaderBuilder, mut reader: R) -> io::Result<()> {
+        match self.stream_buffer {
+            StreamBuffer::Memory => {
+                let mut data = Vec::new();
+                reader.read_to_end(&mut data)?;
+                self.append_builder(builder.size(data.len()), &data)
+            }
+            StreamBuffer::TempFile => {
+                static COUNTER: AtomicUsize = AtomicUsize::new(0);
+                let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+                let path = env::temp_dir().join(format!("ustar_stream_{}_{}", std::process::id(), n));
+                let result = fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create_new(true)
+                    .open(&path)
+                    .and_then(|mut tmp| {
+                        let size = io::copy(&mut reader, &mut tmp)? as usize;
+                        tmp.rewind()?;
+                        self.append_reader(builder.size(size), tmp)
+                    });
+                let _ = fs::remove_file(&path);
+                result
+            }
+        }
+    }
+
+    /// Shared part of `append_builder` and `append_stream`, exactly builder size bytes are taken from data.
+    fn append_reader<R: Read>(&mut self, builder: HeaderBuilder, data: R) -> io::Result<()> {
+        let mut builder = builder;
+        check_name(&builder.name)?;
+        check_name(&builder.linkname)?;
+        self.check_unique(&builder.name)?;
+        let size = builder.size;
+        let mut records = long_names_records(&builder.name, &builder.linkname);
+        records.extend(builder.overflow_records());
+        for (key, value) in &builder.records {
+            records.extend(format_record(key, value));
+        }
+        let name = builder.name.clone();
+        if split_name(&name).is_none() {
+            builder.name = truncate(&name, HeaderProperty::Name.len()).to_string();
+        }
+        builder.linkname = truncate(&builder.linkname, HeaderProperty::Linkname.len()).to_string();
+
+        // Both headers are built before writing anything, so failure leaves no orphan PAX header
+        let header = builder.build()?;
+        if !records.is_empty() {
+            let pax = HeaderBuilder::new(PAX_HEADER_NAME, HeaderType::Xhd)
+                .size(records.len())
+                .build()?;
+            self.write_entry(&pax, &records)?;
+        }
+        self.dst.write_all(&header.to_bytes())?;
+        if io::copy(&mut data.take(size as u64), &mut self.dst)? != size as u64 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        self.dst.write_all(&[0; BLOCK_SIZE][..offset_by_blocks(size) - size])?;
+        self.written += (BLOCK_SIZE + offset_by_blocks(size)) as u64;
+        self.names.insert(name);
+        Ok(())
+    }
+
+    pub fn append_file(&mut self, name: &str, mode: u64, mtime: u128, data: &[u8]) -> io::Result<()> {
+        let builder = HeaderBuilder::new(name, HeaderType::Reg)
+            .mode(mode)
+            .mtime(mtime)
+            .size(data.len());
+        self.append_builder(builder, data)
+    }
+
+    pub fn append_dir(&mut self, name: &str, mode: u64, mtime: u128) -> io::Result<()> {
+        let name = if name.ends_with('/') {
+            name.to_string()
+        } else {
+            format!("{}/", name)
+        };
+        let builder = HeaderBuilder::new(&name, HeaderType::Dir).mode(mode).mtime(mtime);
+        self.append_builder(builder, &[])
+    }
+
+    pub fn append_symlink(&mut self, name: &str, target: &str, mode: u64, mtime: u128) -> io::Result<()> {
+        let builder = HeaderBuilder::new(name, HeaderType::Sym)
+            .linkname(target)
+            .mode(mode)
+            .mtime(mtime);
+        self.append_builder(builder, &[])
+    }
+
+    /// Hard link to the entry that should be already in archive.
+    pub fn append_hardlink(&mut self, name: &str, target: &str, mode: u64, mtime: u128) -> io::Result<()> {
+        if !self.names.contains(target) {
+            if self.strict_links {
+                return Err(invalid_input("hard link target was not appended"));
+            }
+            self.missing_link_targets.push(target.to_string());
+        }
+        let builder = HeaderBuilder::new(name, HeaderType::Link)
+            .linkname(target)
+            .mode(mode)
+            .mtime(mtime);
+        self.append_builder(builder, &[])
+    }
+
+    /// Append directory tree recursively (files, directories and symbolic links) with their mode and mtime.
+    /// Names are relative to `src` joined with `prefix`, prefix itself is added as directory if not empty.
+    /// Entries are appended in name order so output does not depend on file system.
+    pub fn append_dir_all<P: AsRef<Path>>(&mut self, src: P, prefix: &str) -> io::Result<()> {
+        let prefix = prefix.trim_end_matches('/');
+        if !prefix.is_empty() {
+            let meta = fs::metadata(src.as_ref())?;
+            self.append_dir(prefix, file_mode(&meta), file_mtime(&meta))?;
+        }
+        let mut visited = HashSet::new();
+        visited.insert(fs::canonicalize(src.as_ref())?);
+        self.append_tree(src.as_ref(), prefix, &mut visited)
+    }
+
+    /// `visited` keeps canonical paths of directories already packed, followed symbolic links
+    /// could lead to them again (e.g. `dir/loop -> ..`), such links are stored as they are.
+    fn append_tree(&mut self, dir: &Path, prefix: &str, visited: &mut HashSet<PathBuf>) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<fs::DirEntry>>>()?;
+        entries.sort_by_key(|e| e.file_name());
+        for e in entries {
+            let file_name = e.file_name();
+            let file_name = file_name.to_string_lossy();
+            let name = if prefix.is_empty() {
+                file_name.to_string()
+            } else {
+                format!("{}/{}", prefix, file_name)
+            };
+            let path = e.path();
+            let link_meta = fs::symlink_metadata(&path)?;
+            let is_link = link_meta.file_type().is_symlink();
+            let meta = match fs::metadata(&path) {
+                Ok(meta) if is_link && self.follow_symlinks => meta,
+                Err(e) if !is_link || e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => link_meta,
+            };
+            let (mode, mtime) = (file_mode(&meta), file_mtime(&meta));
+            let revisited = meta.is_dir() && !visited.insert(fs::canonicalize(&path)?);
+            if meta.file_type().is_symlink() || (is_link && revisited) {
+                let target = fs::read_link(&path)?;
+                self.append_symlink(&name, &target.to_string_lossy(), mode, mtime)?;
+            } else if meta.is_dir() {
+                self.append_dir(&name, mode, mtime)?;
+                self.append_tree(&path, &name, visited)?;
+            } else if meta.is_file() {
+                let builder = HeaderBuilder::new(&name, HeaderType::Reg)
+                    .mode(mode)
+                    .mtime(mtime)
+                    .size(meta.len() as usize);
+                self.append_reader(builder, fs::File::open(&path)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write archive terminator (two zero blocks) padded with zero blocks up to the multiple of
+    /// blocking factor and give back destination.
+    pub fn finish(mut self) -> io::Result<W> {
+        let record = (self.blocking_factor * BLOCK_SIZE) as u64;
+        let size = self.written + (BLOCK_SIZE * 2) as u64;
+        let padded = size.div_ceil(record) * record;
+        io::copy(&mut io::repeat(0).take(padded - self.written), &mut self.dst)?;
+        self.dst.flush()?;
+        Ok(self.dst)
+    }
+}
+
+#[cfg(unix)]
+fn file_mode(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::PermissionsExt;
+    (meta.permissions().mode() & 0o7777) as u64
+}
+
+#[cfg(not(unix))]
+fn file_mode(meta: &fs::Metadata) -> u64 {
+    match (meta.is_dir(), meta.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+fn file_mtime(meta: &fs::Metadata) -> u128 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as u128)
+}
+
+/// Copy entries from source archive into new one passing each entry through `f`.
+/// Closure gets header with entry data and returns header and data to write (sizes and checksums
+/// are recalculated) or None to drop entry. Returns destination after writing terminator.
+/// Entry data is kept in memory, entries larger than `MAX_ENTRY_SIZE` give `InvalidData` error.
+pub fn transform<R, W, F>(src: &mut R, dst: W, f: F) -> io::Result<W>
+where
+    R: Read + Seek,
+    W: Write,
+    F: FnMut(&Header, Vec<u8>) -> Option<(Header, Vec<u8>)>,
+{
+    let headers = HeadersParser::from(src).collect::<Vec<Header>>();
+    rewrite(headers, src, dst, f)
+}
+
+/// Write entries of all sources one after another followed by a single terminator (like `tar -A`).
+/// Entries are copied as is (with PAX and GNU metadata), each source is read up to its terminator
+/// or the first invalid header. Duplicate names are kept, the last one wins as for appended archives.
+pub fn concat<W, S>(dst: &mut W, sources: &mut [&mut S]) -> io::Result<()>
+where
+    W: Write,
+    S: Read + Seek,
+{
+    for src in sources.iter_mut() {
+        let end = HeadersParser::from(&mut **src)
+            .emit_metadata(true)
+            .map(|h| h.offset + BLOCK_SIZE + offset_by_blocks(h.size))
+            .last()
+            .unwrap_or(0);
+        src.rewind()?;
+        let copied = io::copy(&mut src.by_ref().take(end as u64), dst)?;
+        if copied != end as u64 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+    }
+    dst.write_all(&[0; BLOCK_SIZE * 2])?;
+    dst.flush()
+}
+
+/// Rewrite archive of any supported format (v7, GNU, PAX) as POSIX ustar.
+/// Long names are split into prefix and name fields or written as PAX records if they don't fit,
+/// GNU dumpdir entries become plain directories. Other PAX records are kept and numbers that
+/// don't fit into header fields are written as PAX records too.
+/// Entries of unknown or GNU only type (volume label, multi-volume) give `InvalidInput` error.
+pub fn canonicalize<R, W>(src: &mut R, dst: W) -> io::Result<W>
+where
+    R: Read + Seek,
+    W: Write,
+{
+    let headers = HeadersParser::from(src)
+        .checksum_policy(ChecksumPolicy::ChecksumOnly)
+        .collect::<Vec<Header>>();
+    // Entry data is streamed, so entries of any size are rewritten
+    let mut writer = TarWriter::new(dst);
+    for h in &headers {
+        if matches!(h.typeflag, HeaderType::GnuVolumeLabel | HeaderType::GnuMultiVolume) {
+            return Err(invalid_input("GNU only entry type could not be written as POSIX ustar"));
+        }
+        if h.typeflag == HeaderType::Dumpdir {
+            let mut h = h.clone();
+            h.typeflag = HeaderType::Dir;
+            writer.append_builder(h.to_builder().size(0), &[])?;
+        } else {
+            writer.append_reader(h.to_builder(), entry_reader(src, h)?)?;
+        }
+    }
+    writer.finish()
+}
+
+fn rewrite<R, W, F>(headers: Vec<Header>, src: &mut R, dst: W, mut f: F) -> io::Result<W>
+where
+    R: Read + Seek,
+    W: Write,
+    F: FnMut(&Header, Vec<u8>) -> Option<(Header, Vec<u8>)>,
+{
+    let mut writer = TarWriter::new(dst);
+    for h in &headers {
+        let data = read_data(src, h)?;
+        if let Some((h, data)) = f(h, data) {
+            writer.append_builder(h.to_builder().size(data.len()), &data)?;
+        }
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use hamcrest2::prelude::*;
+
+    use super::*;
+    use super::super::meta::TarMeta;
+    use super::super::read::detect_blocking_factor;
+
+    #[test]
+    fn write_and_read_back() {
+        let long_name = format!("{}/{}", "d".repeat(60), "f".repeat(80));
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_dir("dir", 0o755, 1000).unwrap();
+        writer.append_file("dir/file.txt", 0o600, 2000, b"hello").unwrap();
+        writer.append_file(&long_name, 0o644, 0, b"").unwrap();
+        let mut source = Cursor::new(writer.finish().unwrap());
+
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(3));
+        assert_that!(headers[0].name.as_str(), equal_to("dir/"));
+        assert_that!(headers[0].typeflag, equal_to(HeaderType::Dir));
+        assert_that!(headers[1].mode, equal_to(0o600));
+        assert_that!(headers[1].mtime, equal_to(2000));
+        assert_that!(read_data(&mut source, &headers[1]).unwrap(), equal_to(b"hello".to_vec()));
+        assert_that!(&headers[2].name, equal_to(&long_name));
+    }
+
+    #[test]
+    fn ownership_round_trip() {
+        let builder = HeaderBuilder::new("owned.txt", HeaderType::Reg)
+            .uid(1000)
+            .gid(100)
+            .uname("alice")
+            .gname("users");
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_builder(builder, &[]).unwrap();
+        let mut source = Cursor::new(writer.finish().unwrap());
+
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers[0].uid, equal_to(1000));
+        assert_that!(headers[0].gid, equal_to(100));
+        assert_that!(headers[0].uname.as_str(), equal_to("alice"));
+        assert_that!(headers[0].gname.as_str(), equal_to("users"));
+
+        let long = "u".repeat(33);
+        assert!(HeaderBuilder::new("a", HeaderType::Reg).uname(&long).build().is_err());
+    }
+
+    #[test]
+    fn finish_writes_terminator() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_file("a.txt", 0o644, 0, b"a").unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_that!(bytes.len(), equal_to(BLOCK_SIZE * 4));
+        assert!(bytes[bytes.len() - BLOCK_SIZE * 2..].iter().all(|&b| b == 0));
+
+        let mut source = Cursor::new(bytes);
+        let mut parser = HeadersParser::from(&mut source).stop_at_terminator(true);
+        assert_that!(parser.by_ref().count(), equal_to(1));
+        assert!(parser.is_clean());
+        assert_that!(parser.trailing_zero_blocks(), equal_to(2));
+        assert_that!(parser.trailing_bytes(), equal_to(0));
+        drop(parser);
+        assert_that!(detect_blocking_factor(&mut source), equal_to(1));
+
+        let mut writer = TarWriter::new(Vec::new()).blocking_factor(20);
+        writer.append_file("a.txt", 0o644, 0, b"a").unwrap();
+        let bytes = writer.finish().unwrap();
+        assert_that!(bytes.len(), equal_to(BLOCK_SIZE * 20));
+        assert_that!(detect_blocking_factor(&mut Cursor::new(bytes)), equal_to(20));
+
+        // Without finish there is no terminator
+        let mut bytes = Vec::new();
+        let mut writer = TarWriter::new(&mut bytes);
+        writer.append_file("a.txt", 0o644, 0, b"a").unwrap();
+        drop(writer);
+        let mut source = Cursor::new(bytes);
+        let mut parser = HeadersParser::from(&mut source);
+        assert_that!(parser.by_ref().count(), equal_to(1));
+        assert_that!(parser.trailing_zero_blocks(), equal_to(0));
+    }
+
+    #[test]
+    fn predicted_size_matches_written() {
+        let long_name = format!("{}.txt", "long_name_".repeat(20));
+        let data = vec![b'x'; 700];
+        let entries = [("a.txt", 0), ("b.txt", 700), (long_name.as_str(), 10)];
+
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_file("a.txt", 0o644, 0, &[]).unwrap();
+        writer.append_file("b.txt", 0o644, 0, &data).unwrap();
+        writer.append_file(&long_name, 0o644, 0, &data[..10]).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        assert_that!(TarWriter::predict_size(&entries), equal_to(bytes.len() as u64));
+
+        let mut source = Cursor::new(bytes);
+        let names = HeadersParser::from(&mut source).map(|h| h.name).collect::<Vec<String>>();
+        assert_that!(names, equal_to(vec!["a.txt".to_string(), "b.txt".to_string(), long_name]));
+    }
+
+    #[test]
+    fn hardlink_round_trip() {
+        let mut writer = TarWriter::new(Vec::new()).strict_links(true);
+        writer.append_file("file.txt", 0o644, 0, b"data").unwrap();
+        writer.append_hardlink("link.txt", "file.txt", 0o644, 0).unwrap();
+        let mut source = Cursor::new(writer.finish().unwrap());
+
+        let meta = TarMeta::from(HeadersParser::from(&mut source));
+        let link = meta.get("link.txt").unwrap();
+        assert_that!(link.typeflag, equal_to(HeaderType::Link));
+        assert_that!(link.size, equal_to(0));
+        let target = meta.resolve_link(link).unwrap();
+        assert_that!(target.name.as_str(), equal_to("file.txt"));
+        assert_that!(target.size, equal_to(4));
+    }
+
+    #[test]
+    fn hardlink_missing_target() {
+        let mut writer = TarWriter::new(Vec::new()).strict_links(true);
+        let err = writer.append_hardlink("link.txt", "missing.txt", 0o644, 0).unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::InvalidInput));
+
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_hardlink("link.txt", "missing.txt", 0o644, 0).unwrap();
+        assert_that!(writer.missing_link_targets().to_vec(), equal_to(vec!["missing.txt".to_string()]));
+    }
+
+    #[test]
+    fn unique_names_rejects_duplicate() {
+        let mut writer = TarWriter::new(Vec::new()).unique_names(true);
+        writer.append_file("a.txt", 0o644, 0, b"1").unwrap();
+        let err = writer.append_file("a.txt", 0o644, 0, b"2").unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::AlreadyExists));
+
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_file("a.txt", 0o644, 0, b"1").unwrap();
+        writer.append_file("a.txt", 0o644, 0, b"2").unwrap();
+    }
+
+    #[test]
+    fn append_stream_unknown_length() {
+        for buffer in [StreamBuffer::Memory, StreamBuffer::TempFile] {
+            let mut writer = TarWriter::new(Vec::new()).stream_buffer(buffer);
+            let generated = io::repeat(b'x').take(1000).chain(&b"end"[..]);
+            writer.append_stream(HeaderBuilder::new("gen.txt", HeaderType::Reg), generated).unwrap();
+            writer.append_file("next.txt", 0o644, 0, b"next").unwrap();
+            let mut source = Cursor::new(writer.finish().unwrap());
+
+            let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+            assert_that!(headers.len(), equal_to(2));
+            assert_that!(headers[0].size, equal_to(1003));
+            let data = read_data(&mut source, &headers[0]).unwrap();
+            assert!(data.ends_with(b"xend"));
+            assert_that!(read_data(&mut source, &headers[1]).unwrap(), equal_to(b"next".to_vec()));
+        }
+    }
+
+    #[test]
+    fn concat_two_archives() {
+        let mut first = TarWriter::new(Vec::new());
+        first.append_file("a.txt", 0o644, 0, b"first a").unwrap();
+        first.append_file(&"long/".repeat(30), 0o644, 0, b"long").unwrap();
+        let mut second = TarWriter::new(Vec::new());
+        second.append_file("b.txt", 0o644, 0, b"b").unwrap();
+        second.append_file("a.txt", 0o644, 0, b"second a").unwrap();
+        let mut first = Cursor::new(first.finish().unwrap());
+        let mut second = Cursor::new(second.finish().unwrap());
+
+        let mut output = Vec::new();
+        concat(&mut output, &mut [&mut first, &mut second]).unwrap();
+
+        let mut source = Cursor::new(output);
+        let mut parser = HeadersParser::from(&mut source).stop_at_terminator(true);
+        let names = parser.by_ref().map(|h| h.name).collect::<Vec<String>>();
+        assert_that!(names, equal_to(vec!["a.txt".to_string(), "long/".repeat(30), "b.txt".into(), "a.txt".into()]));
+        assert_that!(parser.trailing_zero_blocks(), equal_to(2));
+        assert_that!(parser.trailing_bytes(), equal_to(0));
+        drop(parser);
+
+        let meta = TarMeta::from_reader(&mut source).unwrap();
+        let a = meta.get("a.txt").unwrap();
+        assert_that!(read_data(&mut source, a).unwrap(), equal_to(b"second a".to_vec()));
+    }
+
+    #[test]
+    fn header_to_builder() {
+        let mut file = std::fs::File::open(super::super::testutil::test_resources_path().join("files_test.tar")).unwrap();
+        let h = HeadersParser::from(&mut file).next().unwrap();
+
+        let ph = h.to_builder().mtime(1_000_000_000).build().unwrap();
+        let rebuilt = Header::from(ph);
+        assert_that!(rebuilt.mtime, equal_to(1_000_000_000));
+        assert_that!(rebuilt.name, equal_to(h.name));
+        assert_that!(rebuilt.size, equal_to(h.size));
+        assert_that!(rebuilt.mode, equal_to(h.mode));
+        assert_that!(rebuilt.uname, equal_to(h.uname));
+    }
+
+    #[test]
+    fn device_numbers_survive_repack() {
+        use super::super::testutil::{archive, entry, header};
+
+        let mut ph = header("dev/sda1", b'4', 0);
+        ph.set_field(HeaderProperty::Devmajor, b"0000010\0");
+        ph.set_field(HeaderProperty::Devminor, b"0000001\0");
+        ph.repair_checksum();
+        let block = ph.to_bytes();
+        let mut source = archive(&[entry(ph, b"")]);
+
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers[0].device(), equal_to(Some((8, 1))));
+
+        // Raw copy keeps the block verbatim
+        let mut copied = Vec::new();
+        concat(&mut copied, &mut [&mut source]).unwrap();
+        assert_that!(&copied[..BLOCK_SIZE], equal_to(&block[..]));
+
+        let rewritten = transform(&mut source, Vec::new(), |h, data| Some((h.clone(), data))).unwrap();
+        let headers = HeadersParser::from(&mut Cursor::new(rewritten)).collect::<Vec<Header>>();
+        assert_that!(headers[0].device(), equal_to(Some((8, 1))));
+    }
+
+    #[test]
+    fn transform_uppercase_text() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_file("text.txt", 0o644, 10, b"hello tar").unwrap();
+        writer.append_file("drop.bin", 0o644, 10, b"binary").unwrap();
+        let mut source = Cursor::new(writer.finish().unwrap());
+
+        let output = transform(&mut source, Vec::new(), |h, data| {
+            if h.name.ends_with(".txt") {
+                Some((h.clone(), data.to_ascii_uppercase()))
+            } else {
+                None
+            }
+        })
+        .unwrap();
+
+        let mut source = Cursor::new(output);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(1));
+        assert_that!(headers[0].mtime, equal_to(10));
+        assert_that!(read_data(&mut source, &headers[0]).unwrap(), equal_to(b"HELLO TAR".to_vec()));
+    }
+
+    #[test]
+    fn failed_append_writes_nothing() {
+        let mut writer = TarWriter::new(Vec::new());
+        let long_name = format!("{}/{}", "d".repeat(120), "f".repeat(120));
+        let builder = HeaderBuilder::new(&long_name, HeaderType::Reg).uname(&"u".repeat(40));
+        assert!(writer.append_builder(builder, b"").is_err());
+
+        let bytes = writer.finish().unwrap();
+        assert_that!(bytes, equal_to(vec![0; BLOCK_SIZE * 2]));
+    }
+
+    #[test]
+    fn reject_nul_and_newline_names() {
+        let mut writer = TarWriter::new(Vec::new());
+        for name in &["bad\0name.txt", "bad\nname.txt"] {
+            let err = writer.append_file(name, 0o644, 0, b"").unwrap_err();
+            assert_that!(err.kind(), equal_to(ErrorKind::InvalidInput));
+        }
+        writer.append_file("good name.txt", 0o644, 0, b"").unwrap();
+        assert!(HeaderBuilder::new("bad\n", HeaderType::Reg).build().is_err());
+        // Nothing was written for rejected entries
+        assert_that!(writer.finish().unwrap().len(), equal_to(BLOCK_SIZE * 3));
+    }
+
+    #[test]
+    fn pack_directory_tree() {
+        let src = std::env::temp_dir().join(format!("ustar_pack_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), b"a").unwrap();
+        fs::write(src.join("sub/b.txt"), b"bb").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_dir_all(&src, "pkg").unwrap();
+        let mut source = Cursor::new(writer.finish().unwrap());
+        fs::remove_dir_all(&src).unwrap();
+
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        let names = headers.iter().map(|h| h.name.as_str()).collect::<Vec<&str>>();
+        #[cfg(unix)]
+        assert_that!(names, equal_to(vec!["pkg/", "pkg/a.txt", "pkg/link", "pkg/sub/", "pkg/sub/b.txt"]));
+        #[cfg(unix)]
+        assert_that!(headers[2].linkname.as_str(), equal_to("a.txt"));
+        #[cfg(not(unix))]
+        assert_that!(names, equal_to(vec!["pkg/", "pkg/a.txt", "pkg/sub/", "pkg/sub/b.txt"]));
+        assert_that!(read_data(&mut source, headers.last().unwrap()).unwrap(), equal_to(b"bb".to_vec()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pack_symlink_loop() {
+        let src = std::env::temp_dir().join(format!("ustar_pack_loop_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&src);
+        fs::create_dir_all(src.join("dir")).unwrap();
+        fs::write(src.join("dir/a.txt"), b"a").unwrap();
+        std::os::unix::fs::symlink("..", src.join("dir/loop")).unwrap();
+        std::os::unix::fs::symlink("dir", src.join("same")).unwrap();
+        std::os::unix::fs::symlink("missing", src.join("gone")).unwrap();
+
+        let mut writer = TarWriter::new(Vec::new()).follow_symlinks(true);
+        writer.append_dir_all(&src, "pkg").unwrap();
+        let mut source = Cursor::new(writer.finish().unwrap());
+        fs::remove_dir_all(&src).unwrap();
+
+        // Links to packed directories and dangling links are kept as links
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        let entries = headers
+            .iter()
+            .map(|h| (h.name.as_str(), h.typeflag, h.linkname.as_str()))
+            .collect::<Vec<(&str, HeaderType, &str)>>();
+        assert_that!(
+            entries,
+            equal_to(vec![
+                ("pkg/", HeaderType::Dir, ""),
+                ("pkg/dir/", HeaderType::Dir, ""),
+                ("pkg/dir/a.txt", HeaderType::Reg, ""),
+                ("pkg/dir/loop", HeaderType::Sym, ".."),
+                ("pkg/gone", HeaderType::Sym, "missing"),
+                ("pkg/same", HeaderType::Sym, "dir"),
+            ])
+        );
+    }
+
+    #[test]
+    fn canonicalize_gnu_long_name() {
+        let path = super::super::testutil::test_resources_path().join("gnu_longname_test.tar");
+        let mut file = std::fs::File::open(path).unwrap();
+        let output = canonicalize(&mut file, Vec::new()).unwrap();
+
+        let mut source = Cursor::new(output);
+        let headers = HeadersParser::from(&mut source).emit_metadata(true).collect::<Vec<Header>>();
+        let types = headers.iter().map(|h| h.typeflag).collect::<Vec<HeaderType>>();
+        assert_that!(types, equal_to(vec![HeaderType::Reg, HeaderType::Xhd, HeaderType::Reg]));
+        assert!(headers.iter().all(|h| &h.magic() == MAGIC));
+        assert_that!(headers[2].name.as_str(), equal_to("pax_dir/this_is_a_very_long_file_name_that_does_not_fit_into_one_hundred_bytes_of_ustar_name_field_at_all.txt"));
+        assert_that!(read_data(&mut source, &headers[2]).unwrap().len(), equal_to(18));
+    }
+
+    #[test]
+    fn canonicalize_keeps_pax_records() {
+        use super::super::testutil::{archive, entry, file, header, pax};
+
+        let mut source = archive(&[
+            pax(&[("SCHILY.xattr.user.tag", "blue"), ("mtime", "1234.5"), ("atime", "1300.25"), ("uid", "3000000")]),
+            file("a.txt", b"data"),
+        ]);
+        let output = canonicalize(&mut source, Vec::new()).unwrap();
+
+        let mut source = Cursor::new(output);
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers.len(), equal_to(1));
+        assert_that!(headers[0].xattrs().get("user.tag").cloned(), equal_to(Some(b"blue".to_vec())));
+        assert_that!(headers[0].pax_record("mtime"), equal_to(Some(&b"1234.5"[..])));
+        assert_that!(headers[0].atime, equal_to(Some(1300)));
+        assert_that!(headers[0].uid, equal_to(3000000));
+        assert_that!(read_data(&mut source, &headers[0]).unwrap(), equal_to(b"data".to_vec()));
+
+        // Numbers too large for header fields fall back to PAX records
+        let builder = HeaderBuilder::new("big.txt", HeaderType::Reg)
+            .uid(1 << 40)
+            .mtime(1 << 40);
+        assert!(builder.build().is_err());
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_builder(builder, b"").unwrap();
+        let mut source = Cursor::new(writer.finish().unwrap());
+        let headers = HeadersParser::from(&mut source).collect::<Vec<Header>>();
+        assert_that!(headers[0].uid, equal_to(1 << 40));
+        assert_that!(headers[0].mtime, equal_to(1 << 40));
+
+        // GNU only entries are not written as POSIX
+        let mut source = archive(&[entry(header("label", b'V', 0), b"")]);
+        let err = canonicalize(&mut source, Vec::new()).unwrap_err();
+        assert_that!(err.kind(), equal_to(ErrorKind::InvalidInput));
+    }
+}
@@ -2,10 +2,26 @@ pub mod common;
 
 // pub use common::{ErrorTar, HeaderProperty, HeaderValidation, PosixHeader, BLOCK_SIZE};
 
-use core::iter::Iterator;
-use std::io::{Read, Seek, SeekFrom};
+use std::io;
 
 #[derive(Debug)]
 pub enum TarError {
     ReadData,
+    Io(io::Error),
+    /// Not enough free space at extraction destination.
+    NotEnoughSpace { required: u64, available: u64 },
+    /// There is no supported digest PAX record for entry.
+    NoDigest,
+    /// Source ended in the middle of a block, `got` bytes of it were read.
+    UnexpectedEof { got: usize },
+    /// Chain of links starting at entry `name` loops back to itself.
+    CircularLink { name: String },
+    /// Padding after entry data at `offset` is not zero.
+    NonZeroPadding { offset: usize },
+}
+
+impl From<io::Error> for TarError {
+    fn from(e: io::Error) -> Self {
+        TarError::Io(e)
+    }
 }